@@ -0,0 +1,74 @@
+use crate::cache_service::cache::{Cache, CacheReader};
+use crate::cache_service::error::CacheError;
+use crate::memdb::memory_database::DatabaseItem;
+use parking_lot::{Mutex, MutexGuard};
+use std::io;
+use std::sync::Arc;
+
+/// Handle for using a `Cache` from async code, see `Cache::into_async`.
+/// Cheap to clone, all clones share the cache.
+/// Values held in memory are returned right away through a `CacheReader`, without waiting
+/// for the lock of the cache. Everything that may read or write the disk locks the cache
+/// on the blocking threads of the tokio runtime via `spawn_blocking`.
+#[derive(Debug, Clone)]
+pub struct AsyncCache {
+    cache: Arc<Mutex<Cache>>,
+    reader: CacheReader,
+}
+
+impl AsyncCache {
+    pub fn new(cache: Cache) -> Self {
+        Self {
+            reader: cache.reader(),
+            cache: Arc::new(Mutex::new(cache)),
+        }
+    }
+
+    /// Locks the cache, e.g. to change its settings.
+    /// Blocks while a get or insert runs, so it shouldn't be held across an `.await`
+    /// or called from async code.
+    /// The in memory gets keep using the `decache_age` and `time_resolution` the cache had
+    /// when this handle was created, like a `CacheReader`.
+    pub fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock()
+    }
+
+    /// Like `Cache::get_cache_value`.
+    /// Disk reads and the backing store are only used from a blocking thread.
+    pub async fn get_cache_value_async(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        if let Some(v) = self.reader.get_from_memory(key)? {
+            return Ok(Some(v.as_ref().clone()));
+        }
+
+        let cache = Arc::clone(&self.cache);
+        let key = key.to_owned();
+        blocking(move || cache.lock().get_cache_value(&key)).await
+    }
+
+    /// Like `Cache::insert_cache_item`, run on a blocking thread,
+    /// as the insert may move other items to disk.
+    pub async fn insert_cache_item_async<V: Into<Vec<u8>>>(
+        &self,
+        key: String,
+        value: V,
+    ) -> Result<Option<DatabaseItem>, CacheError> {
+        let cache = Arc::clone(&self.cache);
+        let value = value.into();
+        blocking(move || cache.lock().insert_cache_item(key, value)).await
+    }
+}
+
+/// Runs `f` with `spawn_blocking`.
+/// A panic in `f` is reported as `CacheError::Poisoned`, as it may have left the cache
+/// half way through a change, a cancelled task as an io error.
+async fn blocking<T, F>(f: F) -> Result<T, CacheError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, CacheError> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(v) => v,
+        Err(e) if e.is_panic() => Err(CacheError::Poisoned),
+        Err(e) => Err(CacheError::Io(io::Error::other(e))),
+    }
+}
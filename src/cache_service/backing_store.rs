@@ -0,0 +1,17 @@
+use crate::cache_service::error::CacheError;
+use std::fmt::Debug;
+
+/// A store the cache falls back to on a miss, e.g. another cache or a database.
+/// Values it returns are inserted into the cache,
+/// inserted values are stored in it if `Cache::set_write_to_backing_store` is set.
+/// Reading and writing are one trait, so a write-through cache always writes to the store
+/// it falls back to.
+pub trait BackingStore: Debug + Send + Sync {
+    /// Returns the value for `key`, or `None` if the store doesn't have it either.
+    fn fetch(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError>;
+
+    /// Stores `value` for `key`. Stores that are only read from can keep the default, which does nothing.
+    fn store(&self, _key: &str, _value: &[u8]) -> Result<(), CacheError> {
+        Ok(())
+    }
+}
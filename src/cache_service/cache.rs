@@ -1,14 +1,28 @@
-use crate::memdb::memory_database::{DatabaseItem, FastDB};
-use crate::tools::get_nano_time;
+#[cfg(feature = "async")]
+use crate::cache_service::async_cache::AsyncCache;
+use crate::cache_service::backing_store::BackingStore;
+use crate::cache_service::error::CacheError;
+use crate::cache_service::stats::{CacheStats, CacheStatsSnapshot, MemoryReport};
+use crate::memdb::memory_database::{
+    entry_overhead, remove_disk_files, DatabaseItem, DiskFormat, FastDB, ItemMetadata,
+};
+use crate::tools::logger;
+use crate::tools::{
+    decode_key_path, encode_key_path, fmt_bytes, get_nano_time, hash_bytes, hash_files, move_dir,
+    remove_empty_parents,
+};
 use directories::ProjectDirs;
+use parking_lot::Mutex;
+use rayon::prelude::*;
 use rayon::{ThreadPool, ThreadPoolBuilder};
-use std::io;
-use std::path::Path;
-extern crate rand;
-use crate::tools::logger;
-use rand::Rng;
-use std::fs::File;
-use std::io::Read;
+use std::collections::HashSet;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 pub const ONE_BYTE: u64 = 1;
 pub const ONE_KIBIBYTE: u64 = ONE_BYTE * 1024;
@@ -25,11 +39,111 @@ pub const ONE_DAY: u64 = ONE_HOUR * 24;
 /// * `LastAccess` : Sorts files by access time and removes oldest
 /// * `LeastUsed` : Removes least used files.
 /// * `Combined` : Sorts by usage and then removes files by age.
-#[derive(Debug)]
+/// * `Fifo` : Removes files in insertion order, regardless of access.
+/// * `SizeWeighted` : Removes the biggest files first, to free the most bytes per removal.
+#[derive(Debug, Clone, Copy)]
 pub enum CleanseStrategy {
     LastAccess,
     LeastUsed,
     Combined,
+    Fifo,
+    SizeWeighted,
+}
+
+/// Codec used for values written to disk.
+/// * `None` : Values are written as is.
+/// * `Zstd` : Better ratio, slower.
+/// * `Lz4` : Faster, lower ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Lz4,
+}
+
+/// Folders the values of keys are written to.
+/// The key is percent-encoded for the path with `encode_key_path`, so it stays below `cache_path`.
+/// * `Flat` : `{cache_path}/{key}`, one folder per key directly below `cache_path`.
+///   Keys in a namespace, `{namespace}/{key}`, go to `{cache_path}/%ns/{namespace}/{key}`,
+///   so a namespace is a single folder that no plain key can be encoded to.
+/// * `Sharded` : `{cache_path}/ab/cd/{key}`, bucketed by the first bytes of the XxHash64 of the key,
+///   so `cache_path` stays small to list with millions of keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskLayout {
+    Flat,
+    Sharded,
+}
+
+impl DiskLayout {
+    /// Folder of the cachefile of `key` below `cache_path`.
+    pub fn folder(self, cache_path: &str, key: &str) -> String {
+        match self {
+            DiskLayout::Flat => match key.split_once('/') {
+                Some((namespace, key)) => format!(
+                    "{}/{}",
+                    DiskLayout::namespace_folder(cache_path, namespace),
+                    encode_key_path(key)
+                ),
+                None => format!("{}/{}", cache_path, encode_key_path(key)),
+            },
+            DiskLayout::Sharded => {
+                let hash = format!("{:016x}", hash_bytes(key.as_bytes()));
+                let key = encode_key_path(key);
+                format!("{}/{}/{}/{}", cache_path, &hash[..2], &hash[2..4], key)
+            }
+        }
+    }
+
+    /// Folder of the keys in `namespace` with `DiskLayout::Flat`.
+    fn namespace_folder(cache_path: &str, namespace: &str) -> String {
+        format!("{}/%ns/{}", cache_path, encode_key_path(namespace))
+    }
+
+    /// Key of the folder at `relative` below `cache_path`, `None` if it isn't one of `folder`.
+    fn key(self, relative: &str) -> Option<String> {
+        match self {
+            DiskLayout::Flat => match relative.strip_prefix("%ns/") {
+                Some(v) => {
+                    let (namespace, key) = v.split_once('/')?;
+                    Some(format!("{}/{}", decode_key_path(namespace)?, decode_key_path(key)?))
+                }
+                None => decode_key_path(relative),
+            },
+            DiskLayout::Sharded => relative.splitn(3, '/').nth(2).and_then(decode_key_path),
+        }
+    }
+}
+
+/// Precision of the access times recorded on gets and inserts.
+/// * `Nanos` : Reads the clock on every access.
+/// * `Millis` : Reads the clock on every access, truncated to milliseconds.
+/// * `Coarse` : Uses a cached time, refreshed by `Cache::refresh_clock`, on resize
+///   and on every run of the expiry sweeper.
+///   Avoids the clock in hot loops, at the cost of LRU precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeResolution {
+    Nanos,
+    Millis,
+    Coarse,
+}
+
+/// Result of `Cache::verify_all`, keys sorted.
+/// * `ok` : The disk file matches the stored hash.
+/// * `corrupt` : The disk file doesn't match the stored hash or can't be read.
+/// * `missing` : The disk file doesn't exist.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub ok: Vec<String>,
+    pub corrupt: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+/// Stop channel and thread of a running background task,
+/// see `Cache::start_expiry_sweeper` and `Cache::start_stats_reporter`.
+#[derive(Debug)]
+struct BackgroundTask {
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
 }
 
 /// Cache manager
@@ -37,6 +151,19 @@ pub enum CleanseStrategy {
 /// * `max_disk_cache` : Amount of disk in bytes to use for caching. [Default: 10 GiB]
 /// * `decache_age` : Amount of seconds after which a file is auto de-cached. [Default: 1 Day]
 /// * `cache_path` : Path to on disk cache [Default: Depends on OS]
+/// * `dedup_identical` : Skip inserts whose value hash matches the stored value. [Default: false]
+/// * `backing_store` : Store to fetch values from on a miss. [Default: None]
+/// * `write_to_backing_store` : Also store inserted values in the `backing_store`. [Default: false]
+/// * `count_index_overhead` : Count the map's unused allocations against `max_ram_cache`. [Default: false]
+/// * `time_resolution` : Precision of recorded access times. [Default: TimeResolution::Nanos]
+/// * `promote_on_disk_evict` : Move the most valuable disk items to free ram instead of dropping them. [Default: false]
+/// * `promote_on_read` : Move disk items that are read back to ram, if they fit. [Default: false]
+/// * `max_chunk_size` : Split values bigger than this into several disk files. [Default: None]
+/// * `disk_compression` : Codec used for values written to disk. [Default: Compression::None]
+/// * `disk_layout` : Folders values are written to. [Default: DiskLayout::Flat]
+/// * `max_entries` : Maximum number of items, in memory and on disk. [Default: None]
+/// * `cleanup_batch_size` : Bytes moved or removed per lock of the database when cleaning up. [Default: 16 MiB]
+/// * `max_item_size` : Size in bytes of the biggest value that is cached. [Default: u64::MAX]
 #[derive(Debug)]
 pub struct Cache {
     max_ram_cache: u64,
@@ -44,211 +171,1985 @@ pub struct Cache {
     decache_age: u64,
     cache_path: String,
     database: FastDB,
-    memdb_size: u64,
-    diskdb_size: u64,
-    management_threadpool: ThreadPool,
+    memdb_size: Arc<AtomicU64>,
+    diskdb_size: Arc<AtomicU64>,
+    management_threadpool: Option<ThreadPool>,
+    dedup_identical: bool,
+    stats: Arc<CacheStats>,
+    cleanse_strategy: CleanseStrategy,
+    backing_store: Option<Arc<dyn BackingStore>>,
+    write_to_backing_store: bool,
+    count_index_overhead: bool,
+    time_resolution: TimeResolution,
+    coarse_now: Arc<AtomicU64>,
+    promote_on_disk_evict: bool,
+    promote_on_read: bool,
+    max_chunk_size: Option<u64>,
+    disk_compression: Compression,
+    disk_layout: DiskLayout,
+    max_entries: Option<u64>,
+    cleanup_batch_size: u64,
+    max_item_size: u64,
+    expiry_sweeper: Mutex<Option<BackgroundTask>>,
+    stats_reporter: Mutex<Option<BackgroundTask>>,
+    shut_down: bool,
+}
+
+/// Shared read access to a `Cache`, see `Cache::reader`.
+/// Cheap to clone and `Send`, so every thread can have its own while the `Cache` keeps inserting.
+/// Gets update the access stats and remove expired items like those of the `Cache`,
+/// but don't ask the backing store on a miss and don't promote disk items.
+/// Expiry and access times use the `decache_age` and `time_resolution` the cache had
+/// when the reader was created, the clock of `TimeResolution::Coarse` is shared with the cache.
+#[derive(Debug, Clone)]
+pub struct CacheReader {
+    database: FastDB,
+    memdb_size: Arc<AtomicU64>,
+    diskdb_size: Arc<AtomicU64>,
+    stats: Arc<CacheStats>,
+    decache_age: u64,
+    time_resolution: TimeResolution,
+    coarse_now: Arc<AtomicU64>,
+}
+
+impl CacheReader {
+    pub fn get_cache_value(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        Ok(self.get_cache_value_shared(key)?.map(unwrap_shared))
+    }
+
+    /// Like `get_cache_value`, but hands out the stored value of in memory items
+    /// instead of a copy.
+    pub fn get_cache_value_shared(&self, key: &str) -> Result<Option<Arc<Vec<u8>>>, CacheError> {
+        let timer = self.stats.start_timer();
+        let hit = access_item(
+            &self.database,
+            &self.memdb_size,
+            &self.diskdb_size,
+            key,
+            clock_now(self.time_resolution, &self.coarse_now, &self.stats),
+            Duration::from_secs(self.decache_age).as_nanos(),
+            hit,
+        )?;
+
+        match hit {
+            Some(Hit::Memory(v)) => {
+                self.stats.record_hit(true);
+                self.stats.get_memory_timings.record(timer);
+                Ok(Some(v))
+            }
+            Some(Hit::Disk(item)) => {
+                self.stats.record_hit(false);
+                let value = load_value(key, &item)?;
+                self.stats.get_disk_timings.record(timer);
+                Ok(value)
+            }
+            None => {
+                self.stats.record_miss();
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the value of `key` if it is held in memory, recording the hit like `get_cache_value`.
+    /// Disk items and misses are left to the caller, without counting an access.
+    #[cfg(feature = "async")]
+    pub(crate) fn get_from_memory(&self, key: &str) -> Result<Option<Arc<Vec<u8>>>, CacheError> {
+        let timer = self.stats.start_timer();
+        let value = access_item(
+            &self.database,
+            &self.memdb_size,
+            &self.diskdb_size,
+            key,
+            clock_now(self.time_resolution, &self.coarse_now, &self.stats),
+            Duration::from_secs(self.decache_age).as_nanos(),
+            |v| {
+                if v.value.is_some() {
+                    v.access_counter += 1;
+                }
+                v.value.clone()
+            },
+        )?;
+        let value = value.flatten();
+        if value.is_some() {
+            self.stats.record_hit(true);
+            self.stats.get_memory_timings.record(timer);
+        }
+        Ok(value)
+    }
+
+    /// Whether `key` is cached, in memory or on disk.
+    /// Doesn't change any access stats and doesn't load the value.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.database.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.database.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.database.is_empty()
+    }
+}
+
+/// An in memory value handed out as a reader, see `Cache::get_cache_value_ref`.
+struct SharedValue(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for SharedValue {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Key of `key` in `namespace`, `{namespace}/{key}`.
+fn namespaced_key(namespace: &str, key: &str) -> Result<String, CacheError> {
+    if namespace.is_empty() || namespace.contains('/') {
+        return Err(CacheError::KeyInvalid(namespace.to_owned()));
+    }
+    Ok(format!("{}/{}", namespace, key))
+}
+
+/// Takes the value out of `v`, copying it only if it is still shared.
+fn unwrap_shared(v: Arc<Vec<u8>>) -> Vec<u8> {
+    Arc::try_unwrap(v).unwrap_or_else(|v| v.as_ref().clone())
+}
+
+/// Loads the value of `item`, reporting disk data that fails to decode or verify as `Corrupt`.
+fn load_value(key: &str, item: &DatabaseItem) -> Result<Option<Arc<Vec<u8>>>, CacheError> {
+    item.load_value().map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => CacheError::Corrupt {
+            key: key.to_owned(),
+        },
+        _ => CacheError::Io(e),
+    })
+}
+
+/// Subtracts `v` from `counter`, stopping at 0.
+/// Going below 0 means the size accounting is off, which is logged instead of wrapping around.
+fn saturating_sub(counter: &AtomicU64, v: u64) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| {
+        Some(c.checked_sub(v).unwrap_or_else(|| {
+            logger::warn(&format!("Size accounting underflow: {} - {}, clamping to 0", c, v));
+            0
+        }))
+    });
+}
+
+/// Subtracts the sizes of the entry of `key` taken out of the database from the counters
+/// and removes its disk folder.
+fn release_item(
+    key: &str,
+    item: &DatabaseItem,
+    memdb_size: &AtomicU64,
+    diskdb_size: &AtomicU64,
+) -> Result<(), CacheError> {
+    saturating_sub(memdb_size, item.get_mem_size() + entry_overhead(key));
+    saturating_sub(diskdb_size, item.get_disk_size()?);
+
+    if let Some(folder) = item.filepath.as_ref().and_then(|f| f.parent()) {
+        if folder.exists() {
+            std::fs::remove_dir_all(folder)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the last access of `key` to `now` and applies `f`, under a single write lock.
+/// Items expired at `now` are removed and released instead.
+fn access_item<T, F: FnOnce(&mut DatabaseItem) -> T>(
+    database: &FastDB,
+    memdb_size: &AtomicU64,
+    diskdb_size: &AtomicU64,
+    key: &str,
+    now: u128,
+    default_ttl: u128,
+    f: F,
+) -> Result<Option<T>, CacheError> {
+    let mut expired = false;
+    let mut result = None;
+    database.update(key, |v| {
+        if v.is_expired(now, default_ttl) {
+            expired = true;
+            return;
+        }
+        v.last_access = now;
+        result = Some(f(v));
+    });
+
+    if expired {
+        logger::debug(&format!("\t\tExpired {:?}", key));
+        // A concurrent insert may have replaced the expired item in the meantime.
+        if let Some(v) = database.remove_if(key, |v| v.is_expired(now, default_ttl)) {
+            release_item(key, &v, memdb_size, diskdb_size)?;
+        }
+    }
+    Ok(result)
+}
+
+/// Where an insert puts the value.
+/// * `Memory` : In memory only, until it is moved to disk.
+/// * `WriteThrough` : In memory and on disk.
+/// * `Disk` : On disk only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placement {
+    Memory,
+    WriteThrough,
+    Disk,
+}
+
+/// What a get needs of an accessed item: the value itself if it is in memory,
+/// otherwise the item to load it from disk.
+enum Hit {
+    Memory(Arc<Vec<u8>>),
+    Disk(DatabaseItem),
+}
+
+/// Counts a get of `item`, cloning only the value of in memory items.
+fn hit(item: &mut DatabaseItem) -> Hit {
+    item.access_counter += 1;
+    match &item.value {
+        Some(v) => Hit::Memory(Arc::clone(v)),
+        None => Hit::Disk(item.clone()),
+    }
+}
+
+/// Builds the management threadpool.
+/// Returns `None` if no threads are requested or the pool can't be created,
+/// in which case management work runs inline on the calling thread.
+fn build_management_threadpool(num_threads: usize) -> Option<ThreadPool> {
+    if num_threads == 0 {
+        logger::warn("No management threads requested, running management work inline");
+        return None;
+    }
+
+    match ThreadPoolBuilder::new().num_threads(num_threads).build() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            logger::warn(&format!(
+                "Couldn't create threadpool, running management work inline: {}",
+                e
+            ));
+            None
+        }
+    }
 }
 
 impl Default for Cache {
     fn default() -> Self {
-        let pd = ProjectDirs::from("net", "soontm", "rust_fast_cache")
-            .expect("Default cache dir not found!");
+        Self::try_default().expect("Couldn't get default cache path")
+    }
+}
 
-        Self {
+impl Cache {
+    /// Like `Cache::default`, but returns an error instead of panicking.
+    /// Uses the cache directory of the OS, or `rust_fast_cache` in the temp directory
+    /// on systems without one, e.g. without a home directory.
+    pub fn try_default() -> io::Result<Self> {
+        let cache_dir = match ProjectDirs::from("net", "soontm", "rust_fast_cache") {
+            Some(pd) => pd.cache_dir().to_path_buf(),
+            None => std::env::temp_dir().join("rust_fast_cache"),
+        };
+        let cache_path = cache_dir.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Default cache path {:?} isn't valid UTF-8", cache_dir),
+            )
+        })?;
+
+        Ok(Self {
             max_ram_cache: ONE_GIBIBYTE,
             max_disk_cache: TEN_GIBIBYTE,
             decache_age: ONE_DAY,
-            cache_path: String::from(
-                pd.cache_dir()
-                    .to_str()
-                    .expect("Couldn't get default cache path"),
-            ),
+            cache_path: String::from(cache_path),
             database: FastDB::default(),
-            memdb_size: 0,
-            diskdb_size: 0,
-            management_threadpool: ThreadPoolBuilder::new()
-                .num_threads(num_cpus::get_physical())
-                .build()
-                .expect("Couldn't create threadpool"),
+            memdb_size: Arc::new(AtomicU64::new(0)),
+            diskdb_size: Arc::new(AtomicU64::new(0)),
+            management_threadpool: build_management_threadpool(num_cpus::get_physical()),
+            dedup_identical: false,
+            stats: Arc::new(CacheStats::default()),
+            cleanse_strategy: CleanseStrategy::Combined,
+            backing_store: None,
+            write_to_backing_store: false,
+            count_index_overhead: false,
+            time_resolution: TimeResolution::Nanos,
+            coarse_now: Arc::new(AtomicU64::new(get_nano_time() as u64)),
+            promote_on_disk_evict: false,
+            promote_on_read: false,
+            max_chunk_size: None,
+            disk_compression: Compression::None,
+            disk_layout: DiskLayout::Flat,
+            max_entries: None,
+            cleanup_batch_size: 16 * ONE_MEBIBYTE,
+            max_item_size: u64::MAX,
+            expiry_sweeper: Mutex::new(None),
+            stats_reporter: Mutex::new(None),
+            shut_down: false,
+        })
+    }
+}
+
+/// Signals a running task to stop and waits for it to finish.
+/// Returns `CacheError::Poisoned` if the task panicked instead.
+fn stop_background_task(task: &Mutex<Option<BackgroundTask>>) -> Result<(), CacheError> {
+    let task = task.lock().take();
+    if let Some(task) = task {
+        let _ = task.stop.send(());
+        task.thread.join().map_err(|_| CacheError::Poisoned)?;
+    }
+    Ok(())
+}
+
+/// Current time in nanoseconds at `time_resolution`, see `Cache::now`.
+fn clock_now(time_resolution: TimeResolution, coarse_now: &AtomicU64, stats: &CacheStats) -> u128 {
+    if time_resolution == TimeResolution::Coarse {
+        return u128::from(coarse_now.load(Ordering::Relaxed));
+    }
+
+    stats.record_clock_read();
+    match time_resolution {
+        TimeResolution::Millis => get_nano_time() / 1_000_000 * 1_000_000,
+        _ => get_nano_time(),
+    }
+}
+
+/// Runs `task` on its own thread until it returns, which it should once the receiver
+/// it is passed gets the stop signal of `stop_background_task`.
+/// Long running loops don't go to the management threadpool, they would block one of its threads.
+fn spawn_background<F: FnOnce(Receiver<()>) + Send + 'static>(task: F) -> BackgroundTask {
+    let (stop, stop_rx) = mpsc::channel();
+    let thread = std::thread::spawn(move || task(stop_rx));
+    BackgroundTask { stop, thread }
+}
+
+/// Counts the items of `database` for a `MemoryReport` of the given usage and limits.
+fn memory_report(
+    database: &FastDB,
+    mem_bytes: u64,
+    disk_bytes: u64,
+    max_ram_cache: u64,
+    max_disk_cache: u64,
+) -> MemoryReport {
+    let (item_count, disk_item_count) = database.item_counts();
+    let utilization = |used: u64, max: u64| {
+        if max == 0 {
+            0.0
+        } else {
+            used as f64 / max as f64
+        }
+    };
+
+    MemoryReport {
+        mem_bytes,
+        disk_bytes,
+        item_count,
+        disk_item_count,
+        mem_utilization: utilization(mem_bytes, max_ram_cache),
+        disk_utilization: utilization(disk_bytes, max_disk_cache),
+    }
+}
+
+fn stats_summary(memory: &MemoryReport, stats: &CacheStatsSnapshot) -> String {
+    let lookups = stats.hits + stats.misses;
+    let hit_ratio = if lookups == 0 {
+        0.0
+    } else {
+        stats.hits as f64 / lookups as f64
+    };
+    format!(
+        "Cache: {} items ({} on disk), ram {} ({:.1}%), disk {} ({:.1}%), hit ratio {:.1}% of {} gets",
+        memory.item_count,
+        memory.disk_item_count,
+        fmt_bytes(memory.mem_bytes),
+        memory.mem_utilization * 100.0,
+        fmt_bytes(memory.disk_bytes),
+        memory.disk_utilization * 100.0,
+        hit_ratio * 100.0,
+        lookups
+    )
+}
+
+impl Drop for Cache {
+    /// Stops the background tasks, unless `Cache::shutdown` already did.
+    fn drop(&mut self) {
+        if self.shut_down {
+            return;
+        }
+        self.stop_expiry_sweeper();
+        self.stop_stats_reporter();
+    }
+}
+
+/// Configures a `Cache`, see `Cache::builder`.
+/// Settings that aren't given keep the defaults of `Cache::default`.
+#[derive(Debug, Clone)]
+pub struct CacheBuilder {
+    max_ram: u64,
+    max_disk: u64,
+    decache_age: Duration,
+    cache_path: Option<PathBuf>,
+    cleanse_strategy: CleanseStrategy,
+    num_management_threads: Option<usize>,
+    capacity: usize,
+    backing_store: Option<Arc<dyn BackingStore>>,
+}
+
+impl Default for CacheBuilder {
+    fn default() -> Self {
+        Self {
+            max_ram: ONE_GIBIBYTE,
+            max_disk: TEN_GIBIBYTE,
+            decache_age: Duration::from_secs(ONE_DAY),
+            cache_path: None,
+            cleanse_strategy: CleanseStrategy::Combined,
+            num_management_threads: None,
+            capacity: 0,
+            backing_store: None,
         }
     }
 }
 
+impl CacheBuilder {
+    /// Amount of ram in bytes to use for caching, must be greater than 0.
+    pub fn max_ram(mut self, max_ram: u64) -> Self {
+        self.max_ram = max_ram;
+        self
+    }
+
+    /// Amount of disk in bytes to use for caching.
+    pub fn max_disk(mut self, max_disk: u64) -> Self {
+        self.max_disk = max_disk;
+        self
+    }
+
+    /// Time without access after which an item is de-cached, in whole seconds.
+    pub fn decache_age(mut self, decache_age: Duration) -> Self {
+        self.decache_age = decache_age;
+        self
+    }
+
+    /// Path to the on disk cache, created on `build` if it doesn't exist.
+    pub fn cache_path(mut self, cache_path: PathBuf) -> Self {
+        self.cache_path = Some(cache_path);
+        self
+    }
+
+    pub fn cleanse_strategy(mut self, cleanse_strategy: CleanseStrategy) -> Self {
+        self.cleanse_strategy = cleanse_strategy;
+        self
+    }
+
+    /// See `Cache::set_management_threads`.
+    pub fn num_management_threads(mut self, num_threads: usize) -> Self {
+        self.num_management_threads = Some(num_threads);
+        self
+    }
+
+    /// Number of items the cache holds before its map has to grow, see `FastDB::with_capacity`.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// See `Cache::set_backing_store`.
+    pub fn backing_store(mut self, backing_store: Arc<dyn BackingStore>) -> Self {
+        self.backing_store = Some(backing_store);
+        self
+    }
+
+    /// Validates the settings and creates the cache.
+    pub fn build(self) -> Result<Cache, CacheError> {
+        if self.max_ram == 0 {
+            return Err(CacheError::InvalidConfig(String::from(
+                "max_ram must be greater than 0",
+            )));
+        }
+
+        let mut cache = Cache::try_default()?;
+        if let Some(path) = self.cache_path {
+            let path = path.to_str().ok_or_else(|| {
+                CacheError::InvalidConfig(format!("cache_path {:?} isn't valid UTF-8", path))
+            })?;
+            std::fs::create_dir_all(path)?;
+            cache.cache_path = String::from(path);
+        }
+        if let Some(num_threads) = self.num_management_threads {
+            cache.set_management_threads(num_threads);
+        }
+        if self.capacity > 0 {
+            cache.database = FastDB::with_capacity(self.capacity);
+        }
+        cache.max_ram_cache = self.max_ram;
+        cache.max_disk_cache = self.max_disk;
+        cache.decache_age = self.decache_age.as_secs();
+        cache.cleanse_strategy = self.cleanse_strategy;
+        cache.backing_store = self.backing_store;
+
+        Ok(cache)
+    }
+}
+
 impl Cache {
+    pub fn builder() -> CacheBuilder {
+        CacheBuilder::default()
+    }
+
     /// Set or change the cache path
+    /// With `migrate`, the folders of items on disk are moved to the new path.
+    /// Without, they stay at the old path and are still read from there,
+    /// only values written from now on go to the new path.
     /// WARNING: Old path will not be cleared !
-    pub fn set_cache_path(&mut self, new_cache_path: String) {
+    pub fn set_cache_path(
+        &mut self,
+        new_cache_path: String,
+        migrate: bool,
+    ) -> Result<(), CacheError> {
         if !Path::new(&new_cache_path).exists() {
             logger::log("Cache path does not exist, creating!");
-            std::fs::create_dir_all(&new_cache_path).expect("Could not create cache path");
+            std::fs::create_dir_all(&new_cache_path)?;
+        }
+        if migrate && new_cache_path != self.cache_path {
+            self.migrate_disk_items(&new_cache_path)?;
         }
         self.cache_path = new_cache_path;
+        Ok(())
     }
 
-    /// Change cache settings.
-    /// * `max_ram_cache` : Amount of ram in bytes to use for caching. [Default: 1GiB]
-    /// * `max_disk_cache` : Amount of disk in bytes to use for caching. [Default: 10 GiB]
-    /// * `cleanse_strategy` : How to remove cache data, if full. [Default CleanseStrategy::Combined]
-    pub fn resize_cache(
-        &mut self,
-        max_ram_cache: Option<u64>,
-        max_disk_cache: Option<u64>,
-        cleanse_strategy: Option<CleanseStrategy>,
-    ) {
-        logger::warn("Resizing cache, no requests will be handled !");
-        let new_max_ram = max_ram_cache.unwrap_or(ONE_GIBIBYTE);
-        let new_max_disk = max_disk_cache.unwrap_or(TEN_GIBIBYTE);
-        let c_strat = cleanse_strategy.unwrap_or(CleanseStrategy::Combined);
+    /// Moves the folders of all disk items from `cache_path` to `new_cache_path`
+    /// and points the items at their new files.
+    fn migrate_disk_items(&mut self, new_cache_path: &str) -> Result<(), CacheError> {
+        for (k, v) in self.database.disk_items() {
+            let old_folder = PathBuf::from(self.disk_layout.folder(&self.cache_path, &k));
+            let new_folder = PathBuf::from(self.disk_layout.folder(new_cache_path, &k));
+            if !old_folder.is_dir() {
+                continue;
+            }
 
-        if new_max_ram < self.max_ram_cache {
-            self.cleanup_mem_cache(&c_strat, new_max_ram)
-                .expect("Couldn't cleanup memory");
+            move_dir(&old_folder, &new_folder)?;
+            let file_name = v.filepath.as_ref().and_then(|f| f.file_name());
+            let filepath = new_folder.join(file_name.unwrap_or_else(|| "cachefile".as_ref()));
+            self.database.update(&k, |item| item.filepath = Some(filepath));
+            logger::debug(&format!("\t\tMoved {:?} to {:?}", &k, &new_folder));
         }
+        Ok(())
+    }
 
-        if new_max_disk < self.max_disk_cache {
-            self.cleanup_disk_cache(&c_strat, new_max_disk)
-                .expect("Couldn't cleanup disk");
+    /// Bytes currently used in memory: the items, their keys and the estimated
+    /// per entry overhead of the map, see `memory_database::ENTRY_OVERHEAD`.
+    /// Includes `index_overhead_bytes` if `count_index_overhead` is set.
+    pub fn memory_used(&self) -> u64 {
+        let memdb_size = self.memdb_size.load(Ordering::Relaxed);
+        if self.count_index_overhead {
+            memdb_size + self.index_overhead_bytes()
+        } else {
+            memdb_size
         }
+    }
 
-        self.max_ram_cache = new_max_ram;
-        self.max_disk_cache = new_max_disk;
-        logger::warn("Resized cache, requests will be handled again !");
+    /// Estimated memory allocated by the map holding the items but not yet used by them,
+    /// see `FastDB::index_overhead_bytes`.
+    /// The map's memory for each item is already part of `memory_used`.
+    pub fn index_overhead_bytes(&self) -> u64 {
+        self.database.index_overhead_bytes()
     }
 
-    fn cleanup_mem_cache(
-        &mut self,
-        cleanse_strategy: &CleanseStrategy,
-        new_max_cache: u64,
-    ) -> io::Result<()> {
-        if self.memdb_size <= new_max_cache {
-            return Ok(());
+    /// Also count the memory the map has allocated but doesn't use yet against `max_ram_cache`,
+    /// see `index_overhead_bytes`.
+    pub fn set_count_index_overhead(&mut self, count_index_overhead: bool) {
+        self.count_index_overhead = count_index_overhead;
+    }
+
+    /// Bytes currently used on disk.
+    pub fn disk_used(&self) -> u64 {
+        self.diskdb_size.load(Ordering::Relaxed)
+    }
+
+    /// Whether `key` is cached, in memory or on disk.
+    /// Doesn't change any access stats and doesn't load the value.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.database.contains_key(key)
+    }
+
+    /// Returns the current usage of the cache, counting the items under a read lock.
+    pub fn memory_report(&self) -> MemoryReport {
+        memory_report(
+            &self.database,
+            self.memory_used(),
+            self.disk_used(),
+            self.max_ram_cache,
+            self.max_disk_cache,
+        )
+    }
+
+    /// Moves the cache into a handle for async code, see `AsyncCache`.
+    #[cfg(feature = "async")]
+    pub fn into_async(self) -> AsyncCache {
+        AsyncCache::new(self)
+    }
+
+    /// Returns a handle for reading from the cache from other threads, see `CacheReader`.
+    pub fn reader(&self) -> CacheReader {
+        CacheReader {
+            database: self.database.clone(),
+            memdb_size: Arc::clone(&self.memdb_size),
+            diskdb_size: Arc::clone(&self.diskdb_size),
+            stats: Arc::clone(&self.stats),
+            decache_age: self.decache_age,
+            time_resolution: self.time_resolution,
+            coarse_now: Arc::clone(&self.coarse_now),
         }
+    }
 
-        let to_clean = self
-            .memdb_size
-            .checked_sub(new_max_cache)
-            .expect("New max_cache < memdb size");
+    /// Returns the keys of all cached items, in no particular order.
+    pub fn keys(&self) -> Vec<String> {
+        self.database.keys()
+    }
 
-        logger::log("[CLEANING MEMDB]");
-        logger::log(&format!("\tMemory used: {:?}", &self.memdb_size));
-        logger::log(&format!("\tMemory max: {:?}", new_max_cache));
-        logger::log(&format!("\tCleaning up: {:?}", to_clean));
-        logger::log(&format!("\tStartegy: {:?}", cleanse_strategy));
+    /// Returns `(key, last_access, access_counter, is_on_disk, created_at)` for all cached items.
+    /// Doesn't change any access stats and doesn't load the values.
+    pub fn iter_metadata(&self) -> Vec<(String, u128, u64, bool, u128)> {
+        self.database.metadata()
+    }
 
-        let disk_size =
-            self.database
-                .cleanup_mem(cleanse_strategy, to_clean, &self.cache_path.to_owned())?;
+    /// Number of cached items, in memory and on disk.
+    pub fn len(&self) -> usize {
+        self.database.len()
+    }
 
-        self.diskdb_size += disk_size;
+    pub fn is_empty(&self) -> bool {
+        self.database.is_empty()
+    }
 
-        Ok(())
+    /// Record the duration of gets, inserts, removes and resizes in the stats.
+    pub fn set_instrument(&mut self, instrument: bool) {
+        self.stats.set_instrument(instrument);
     }
 
-    fn cleanup_disk_cache(
-        &mut self,
-        cleanse_strategy: &CleanseStrategy,
-        new_max_disk: u64,
-    ) -> io::Result<()> {
-        if self.diskdb_size <= new_max_disk {
-            return Ok(());
+    pub fn stats(&self) -> CacheStatsSnapshot {
+        let mut snapshot = self.stats.snapshot();
+        snapshot.lock_contention = self.database.lock_contention();
+        snapshot
+    }
+
+    /// Measure how long gets and inserts wait for the database lock.
+    pub fn set_lock_contention_stats(&mut self, lock_contention_stats: bool) {
+        self.database.set_lock_contention_stats(lock_contention_stats);
+    }
+
+    /// Returns up to `n` keys, in the order the current `CleanseStrategy` would evict them.
+    /// Doesn't change any access stats.
+    pub fn eviction_candidates(&self, n: usize) -> Vec<String> {
+        self.database.eviction_candidates(&self.cleanse_strategy, n)
+    }
+
+    /// Protects `key` from eviction until `until`: it is evicted only after all other items.
+    /// Unlike a pin, the boost expires on its own.
+    pub fn boost(&mut self, key: &str, until: Instant) {
+        let remaining = until.saturating_duration_since(Instant::now());
+        self.database.boost(key, get_nano_time() + remaining.as_nanos());
+    }
+
+    /// Rebuild the management threadpool with `num_threads` threads.
+    /// With 0 threads, management work runs inline on the calling thread.
+    pub fn set_management_threads(&mut self, num_threads: usize) {
+        self.management_threadpool = build_management_threadpool(num_threads);
+    }
+
+    /// Runs `f` on the management threadpool, or inline if there is none.
+    fn run_management<R: Send, F: FnOnce() -> R + Send>(&self, f: F) -> R {
+        match &self.management_threadpool {
+            Some(v) => v.install(f),
+            None => f(),
         }
+    }
 
-        let to_clean = self
-            .diskdb_size
-            .checked_sub(new_max_disk)
-            .expect("New max_cache < memdb size");
+    /// Number of management threads, 0 if management work runs inline.
+    pub fn management_threads(&self) -> usize {
+        self.management_threadpool
+            .as_ref()
+            .map_or(0, |v| v.current_num_threads())
+    }
 
-        logger::log("[CLEANING DISKDB]");
-        logger::log(&format!("\tDisk used: {:?}", &self.diskdb_size));
-        logger::log(&format!("\tDisk max: {:?}", new_max_disk));
-        logger::log(&format!("\tCleaning up: {:?}", to_clean));
-        logger::log(&format!("\tStartegy: {:?}", cleanse_strategy));
+    /// When the disk cache is cleaned up, first move the most valuable disk items
+    /// into free ram, and only drop what is still over the limit.
+    pub fn set_promote_on_disk_evict(&mut self, promote_on_disk_evict: bool) {
+        self.promote_on_disk_evict = promote_on_disk_evict;
+    }
 
-        self.database
-            .cleanup_disk(cleanse_strategy, to_clean, &self.cache_path.to_owned())?;
+    /// When a value is read from disk and fits into the free ram, keep it in memory
+    /// and remove its disk files, so later reads are served from memory.
+    pub fn set_promote_on_read(&mut self, promote_on_read: bool) {
+        self.promote_on_read = promote_on_read;
+    }
 
-        Ok(())
+    /// Sets the size above which values are split into several disk files, `None` to never split.
+    /// Only applies to values written after the call.
+    pub fn set_max_chunk_size(&mut self, max_chunk_size: Option<u64>) {
+        self.max_chunk_size = max_chunk_size;
     }
 
-    pub fn remove_cache_item(&mut self, key: &str) -> io::Result<Option<DatabaseItem>> {
-        let dbi = self.database.get(key)?;
-        match dbi {
-            Some(v) => {
-                let size = v.get_mem_size();
-                self.database.del(key)?;
-                self.memdb_size -= size;
+    /// Sets the codec used for values written to disk. Reads are decompressed transparently.
+    /// Only applies to values written after the call, files already on disk keep their codec.
+    pub fn set_disk_compression(&mut self, disk_compression: Compression) {
+        self.disk_compression = disk_compression;
+    }
 
-                if v.filepath.is_some() {
-                    std::fs::remove_dir_all(v.filepath.expect("Filepath not existent :("))?;
-                }
+    /// Sets the folders values are written to, see `DiskLayout`.
+    /// Only applies to values written after the call, items on disk keep their files.
+    pub fn set_disk_layout(&mut self, disk_layout: DiskLayout) {
+        self.disk_layout = disk_layout;
+    }
 
-                Ok(None)
-            }
-            None => Ok(None),
+    fn disk_format(&self) -> DiskFormat {
+        DiskFormat {
+            max_chunk_size: self.max_chunk_size,
+            compression: self.disk_compression,
+            layout: self.disk_layout,
         }
     }
 
-    pub fn insert_cache_item(
-        &mut self,
-        key: String,
-        value: Vec<u8>,
-    ) -> io::Result<Option<DatabaseItem>> {
-        let mut rng = rand::thread_rng();
+    /// Change the precision of the access times recorded on gets and inserts.
+    pub fn set_time_resolution(&mut self, time_resolution: TimeResolution) {
+        self.time_resolution = time_resolution;
+        self.refresh_clock();
+    }
 
-        self.remove_cache_item(&key.clone())?;
+    /// Updates the cached time used by `TimeResolution::Coarse`.
+    pub fn refresh_clock(&self) {
+        self.stats.record_clock_read();
+        self.coarse_now.store(get_nano_time() as u64, Ordering::Relaxed);
+    }
 
-        //let file_path:PathBuf = PathBuf::from(format!("{}/{}",&self.cache_path ,&key));
-        let dbi = DatabaseItem {
-            value: Some(value),
-            last_access: get_nano_time(),
-            access_counter: rng.gen_range(0, 3), //TODO remove after testing !
-            filepath: None,
-        };
-        self.memdb_size += dbi.get_mem_size();
-        Ok(self.database.set(key, dbi)?)
+    /// Current time in nanoseconds, at the configured `time_resolution`.
+    /// Reads of the system clock are counted in the stats.
+    fn now(&self) -> u128 {
+        clock_now(self.time_resolution, &self.coarse_now, &self.stats)
     }
 
-    pub fn get_cache_item(&mut self, key: String) -> io::Result<Option<DatabaseItem>> {
-        let f = self.database.get(&key)?;
-        if f.is_none() {
-            return Ok(None);
-        }
+    /// Periodically removes every item that wasn't accessed for its ttl or `decache_age`,
+    /// in memory and on disk.
+    /// Every run refreshes the clock of `TimeResolution::Coarse` first and checks the items
+    /// against that time, so items stamped with it aren't older than the last run.
+    /// Runs on its own thread, so it doesn't hold up the management threadpool.
+    /// A running sweeper is stopped first.
+    pub fn start_expiry_sweeper(&self, interval: Duration) {
+        self.stop_expiry_sweeper();
 
-        let mut fx = f.expect("Some is None !");
-        fx.last_access = get_nano_time();
-        fx.access_counter += 1;
+        let mut database = self.database.clone();
+        let memdb_size = Arc::clone(&self.memdb_size);
+        let diskdb_size = Arc::clone(&self.diskdb_size);
+        let coarse_now = Arc::clone(&self.coarse_now);
+        let stats = Arc::clone(&self.stats);
+        let decache_age = Duration::from_secs(self.decache_age).as_nanos();
 
-        self.database.set(key, fx.clone())?;
+        let sweep = move |stop: Receiver<()>| {
+            while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+                stats.record_clock_read();
+                let now = get_nano_time();
+                coarse_now.store(now as u64, Ordering::Relaxed);
+                let (mem_freed, disk_freed) = database.remove_expired(now, decache_age);
+                saturating_sub(&memdb_size, mem_freed);
+                saturating_sub(&diskdb_size, disk_freed);
+            }
+        };
 
-        Ok(Some(fx))
+        *self.expiry_sweeper.lock() = Some(spawn_background(sweep));
     }
 
-    pub fn get_cache_value(&mut self, key: String) -> io::Result<Option<Vec<u8>>> {
-        let cache_item = self.get_cache_item(key)?;
-        if cache_item.is_none() {
-            return Ok(None);
+    /// Stops the expiry sweeper and waits for a running sweep to finish.
+    /// Does nothing if no sweeper is running.
+    pub fn stop_expiry_sweeper(&self) {
+        if let Err(e) = stop_background_task(&self.expiry_sweeper) {
+            logger::error(&format!("Expiry sweeper failed: {}", e));
         }
+    }
 
-        let fxi = cache_item.expect("Some is none");
-        match fxi.value {
-            None => match fxi.filepath {
-                None => Ok(None),
-                Some(v) => {
-                    if Path::new(&v).exists() {
-                        let mut f = File::open(&v)?;
-                        let mut buff: Vec<u8> = vec![];
-                        f.read_to_end(&mut buff)?;
-                        logger::log("From disk");
-                        Ok(Some(buff))
-                    } else {
-                        Ok(None)
-                    }
+    /// Periodically logs `stats_summary`, computed from the state of the cache at that time.
+    /// Runs like the expiry sweeper, a running reporter is stopped first.
+    pub fn start_stats_reporter(&self, interval: Duration) {
+        self.stop_stats_reporter();
+
+        let database = self.database.clone();
+        let memdb_size = Arc::clone(&self.memdb_size);
+        let diskdb_size = Arc::clone(&self.diskdb_size);
+        let stats = Arc::clone(&self.stats);
+        let count_index_overhead = self.count_index_overhead;
+        let (max_ram_cache, max_disk_cache) = (self.max_ram_cache, self.max_disk_cache);
+
+        let report = move |stop: Receiver<()>| {
+            while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+                let mut mem_bytes = memdb_size.load(Ordering::Relaxed);
+                if count_index_overhead {
+                    mem_bytes += database.index_overhead_bytes();
                 }
-            },
+                let memory = memory_report(
+                    &database,
+                    mem_bytes,
+                    diskdb_size.load(Ordering::Relaxed),
+                    max_ram_cache,
+                    max_disk_cache,
+                );
+                logger::log(&stats_summary(&memory, &stats.snapshot()));
+            }
+        };
+
+        *self.stats_reporter.lock() = Some(spawn_background(report));
+    }
+
+    /// Stops the stats reporter. Does nothing if no reporter is running.
+    pub fn stop_stats_reporter(&self) {
+        if let Err(e) = stop_background_task(&self.stats_reporter) {
+            logger::error(&format!("Stats reporter failed: {}", e));
+        }
+    }
+
+    /// Stops the expiry sweeper and the stats reporter, waiting for a running sweep to finish,
+    /// and moves every in memory value to disk, so `rebuild_from_disk` can recover all items.
+    /// Dropping the cache without it only stops the background tasks.
+    /// Returns `CacheError::Poisoned` if a background task panicked, once the values are on disk.
+    pub fn shutdown(mut self) -> Result<(), CacheError> {
+        let sweeper = stop_background_task(&self.expiry_sweeper);
+        let reporter = stop_background_task(&self.stats_reporter);
+        self.shut_down = true;
+
+        self.flush_to_disk()?;
+        sweeper.and(reporter)
+    }
+
+    /// One line summary of the usage and hit ratio of the cache, as logged by the stats reporter.
+    pub fn stats_summary(&self) -> String {
+        stats_summary(&self.memory_report(), &self.stats())
+    }
+
+    /// Set the time without access after which items without their own ttl are de-cached,
+    /// in whole seconds. A running expiry sweeper keeps using the age it was started with.
+    pub fn set_decache_age(&mut self, age: Duration) {
+        self.decache_age = age.as_secs();
+    }
+
+    /// Skip re-inserting values that are identical to the stored ones,
+    /// if they have the same ttl and would stay where they are, in memory or on disk.
+    /// The skipped insert still counts as an access of the stored item.
+    /// Identity is decided by the XxHash64 of the value.
+    pub fn set_dedup_identical(&mut self, dedup_identical: bool) {
+        self.dedup_identical = dedup_identical;
+    }
+
+    /// Set the store that is consulted when a key isn't cached.
+    pub fn set_backing_store(&mut self, backing_store: Arc<dyn BackingStore>) {
+        self.backing_store = Some(backing_store);
+    }
+
+    /// Store inserted values in the backing store as well, before they are cached.
+    /// Values fetched from the backing store aren't stored again.
+    pub fn set_write_to_backing_store(&mut self, write_to_backing_store: bool) {
+        self.write_to_backing_store = write_to_backing_store;
+    }
+
+    /// Change cache settings.
+    /// * `max_ram_cache` : Amount of ram in bytes to use for caching. [Default: 1GiB]
+    /// * `max_disk_cache` : Amount of disk in bytes to use for caching. [Default: 10 GiB]
+    /// * `cleanse_strategy` : How to remove cache data, if full. [Default CleanseStrategy::Combined]
+    pub fn resize_cache(
+        &mut self,
+        max_ram_cache: Option<u64>,
+        max_disk_cache: Option<u64>,
+        cleanse_strategy: Option<CleanseStrategy>,
+    ) -> Result<(), CacheError> {
+        let timer = self.stats.start_timer();
+        self.refresh_clock();
+        logger::warn("Resizing cache");
+        let new_max_ram = max_ram_cache.unwrap_or(ONE_GIBIBYTE);
+        let new_max_disk = max_disk_cache.unwrap_or(TEN_GIBIBYTE);
+        let c_strat = cleanse_strategy.unwrap_or(CleanseStrategy::Combined);
+
+        if new_max_ram < self.max_ram_cache {
+            self.cleanup_mem_cache(&c_strat, new_max_ram)?;
+        }
+        self.max_ram_cache = new_max_ram;
+
+        if new_max_disk < self.max_disk_cache {
+            self.cleanup_disk_cache(&c_strat, new_max_disk)?;
+        }
+
+        self.max_disk_cache = new_max_disk;
+        self.cleanse_strategy = c_strat;
+        self.stats.resize_timings.record(timer);
+        logger::warn("Resized cache");
+        Ok(())
+    }
+
+    /// Set the bytes moved to disk or removed from it per lock of the database
+    /// when cleaning up. The lock is released between batches,
+    /// so gets and inserts aren't held up for a whole resize. At least 1 byte.
+    pub fn set_cleanup_batch_size(&mut self, cleanup_batch_size: u64) {
+        self.cleanup_batch_size = cleanup_batch_size.max(1);
+    }
+
+    /// Refuse values bigger than `max_item_size` bytes with `CacheError::ValueTooLarge`,
+    /// so a single huge value can't evict the rest of the cache.
+    pub fn set_max_item_size(&mut self, max_item_size: u64) {
+        self.max_item_size = max_item_size;
+    }
+
+    /// Limits the number of items, in memory and on disk, `None` for no limit.
+    /// Items over the limit are removed in the order of the current `CleanseStrategy`,
+    /// now and whenever an insert exceeds it.
+    pub fn set_max_entries(&mut self, max_entries: Option<u64>) -> Result<(), CacheError> {
+        self.max_entries = max_entries;
+        let cleanse_strategy = self.cleanse_strategy;
+        self.cleanup_entries(&cleanse_strategy, 0)?;
+        Ok(())
+    }
+
+    /// Removes items until there is room for `reserve` more within `max_entries`.
+    /// Returns the number of removed items.
+    fn cleanup_entries(
+        &mut self,
+        cleanse_strategy: &CleanseStrategy,
+        reserve: u64,
+    ) -> Result<u64, CacheError> {
+        let max_entries = match self.max_entries {
+            Some(v) => v.saturating_sub(reserve),
+            None => return Ok(0),
+        };
+        let entries = self.database.len() as u64;
+        if entries <= max_entries {
+            return Ok(0);
+        }
+
+        let to_remove = entries - max_entries;
+        let (mem_freed, disk_freed) = self
+            .database
+            .remove_entries(cleanse_strategy, to_remove as usize);
+
+        saturating_sub(&self.memdb_size, mem_freed);
+        saturating_sub(&self.diskdb_size, disk_freed);
+        self.stats.record_evictions(to_remove);
+
+        Ok(to_remove)
+    }
+
+    /// Moves items to disk until memory usage is at or below `new_max_cache`,
+    /// in batches of `cleanup_batch_size` bytes.
+    /// Usage is checked again before each batch, so items inserted meanwhile are accounted for.
+    /// Returns the requested and the actually freed amount of bytes.
+    fn cleanup_mem_cache(
+        &mut self,
+        cleanse_strategy: &CleanseStrategy,
+        new_max_cache: u64,
+    ) -> Result<(u64, u64), CacheError> {
+        let memory_used = self.memory_used();
+        if memory_used <= new_max_cache {
+            return Ok((0, 0));
+        }
+
+        let to_clean = memory_used
+            .checked_sub(new_max_cache)
+            .expect("New max_cache < memdb size");
+
+        logger::log("[CLEANING MEMDB]");
+        logger::log(&format!("\tMemory used: {:?}", memory_used));
+        logger::log(&format!("\tMemory max: {:?}", new_max_cache));
+        logger::log(&format!("\tCleaning up: {:?}", to_clean));
+        logger::log(&format!("\tStartegy: {:?}", cleanse_strategy));
+
+        let mut freed: u64 = 0;
+        loop {
+            let memory_used = self.memory_used();
+            if memory_used <= new_max_cache {
+                break;
+            }
+            let batch = (memory_used - new_max_cache).min(self.cleanup_batch_size);
+
+            let (batch_freed, disk_size, _skipped) =
+                self.database
+                    .cleanup_mem(
+                        cleanse_strategy,
+                        batch,
+                        &self.cache_path.to_owned(),
+                        self.disk_format(),
+                        self.management_threadpool.as_ref(),
+                    )?;
+
+            saturating_sub(&self.memdb_size, batch_freed);
+            self.diskdb_size.fetch_add(disk_size, Ordering::Relaxed);
+            freed += batch_freed;
+            if batch_freed == 0 {
+                break;
+            }
+        }
+        self.stats.record_eviction(to_clean, freed);
+
+        Ok((to_clean, freed))
+    }
+
+    /// Moves every item that wasn't accessed for `older_than` to disk, regardless of the ram limit.
+    /// Returns the bytes written to disk.
+    pub fn flush_cold(&mut self, older_than: Duration) -> Result<u64, CacheError> {
+        self.spill_older_than(get_nano_time().saturating_sub(older_than.as_nanos()))
+    }
+
+    /// Moves every in memory value to disk, regardless of the ram limit, e.g. before shutting down.
+    /// Items already on disk are not written again.
+    /// Returns the bytes written to disk.
+    pub fn flush_to_disk(&mut self) -> Result<u64, CacheError> {
+        self.spill_older_than(u128::MAX)
+    }
+
+    fn spill_older_than(&mut self, cutoff: u128) -> Result<u64, CacheError> {
+        let (freed, disk_size, _skipped) = self
+            .database
+            .spill_older_than(
+                cutoff,
+                &self.cache_path.to_owned(),
+                self.disk_format(),
+                self.management_threadpool.as_ref(),
+            )?;
+
+        saturating_sub(&self.memdb_size, freed);
+        self.diskdb_size.fetch_add(disk_size, Ordering::Relaxed);
+
+        Ok(disk_size)
+    }
+
+    /// Adds the cachefile folders found below `cache_path` that aren't in the index,
+    /// e.g. after a crash, as disk items keyed by their decoded folder name, see `encode_key_path`.
+    /// The files are assumed to be written with the current `disk_compression` and `disk_layout`,
+    /// folders that aren't where the layout puts their key are skipped.
+    /// Returns the number of recovered items.
+    pub fn rebuild_from_disk(&mut self) -> Result<u64, CacheError> {
+        let root = PathBuf::from(&self.cache_path);
+        let compressed = self.disk_compression != Compression::None;
+        let mut folders = vec![root.clone()];
+        let mut recovered: u64 = 0;
+
+        while let Some(folder) = folders.pop() {
+            for entry in std::fs::read_dir(&folder)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    folders.push(path);
+                }
+            }
+            if folder == root {
+                continue;
+            }
+
+            let relative = match folder.strip_prefix(&root).ok().and_then(|v| v.to_str()) {
+                Some(v) => v.replace(std::path::MAIN_SEPARATOR, "/"),
+                None => continue,
+            };
+            let key = match self.disk_layout.key(&relative) {
+                Some(v) => v,
+                None => continue,
+            };
+            if Path::new(&self.disk_layout.folder(&self.cache_path, &key)) != folder {
+                continue;
+            }
+            if self.database.contains_key(&key) {
+                continue;
+            }
+            if let Some(item) = DatabaseItem::from_disk(&folder, compressed) {
+                let mem_size = item.get_mem_size() + entry_overhead(&key);
+                self.memdb_size.fetch_add(mem_size, Ordering::Relaxed);
+                self.diskdb_size.fetch_add(item.get_disk_size()?, Ordering::Relaxed);
+                logger::debug(&format!("\t\tRecovered {:?}", &key));
+                self.database.set(key, item)?;
+                recovered += 1;
+            }
+        }
+
+        Ok(recovered)
+    }
+
+    /// Removes items from disk until disk usage is at or below `new_max_disk`,
+    /// in batches of `cleanup_batch_size` bytes like `cleanup_mem_cache`.
+    /// Returns the requested and the actually freed amount of bytes.
+    fn cleanup_disk_cache(
+        &mut self,
+        cleanse_strategy: &CleanseStrategy,
+        new_max_disk: u64,
+    ) -> Result<(u64, u64), CacheError> {
+        let disk_used = self.disk_used();
+        if disk_used <= new_max_disk {
+            return Ok((0, 0));
+        }
+
+        let to_clean = disk_used
+            .checked_sub(new_max_disk)
+            .expect("New max_cache < memdb size");
+
+        logger::log("[CLEANING DISKDB]");
+        logger::log(&format!("\tDisk used: {:?}", disk_used));
+        logger::log(&format!("\tDisk max: {:?}", new_max_disk));
+        logger::log(&format!("\tCleaning up: {:?}", to_clean));
+        logger::log(&format!("\tStartegy: {:?}", cleanse_strategy));
+
+        let mut freed: u64 = 0;
+
+        if self.promote_on_disk_evict {
+            let headroom = self.max_ram_cache.saturating_sub(self.memory_used());
+            let (added, promoted) =
+                self.database
+                    .promote_disk_items(cleanse_strategy, to_clean, headroom)?;
+
+            self.memdb_size.fetch_add(added, Ordering::Relaxed);
+            saturating_sub(&self.diskdb_size, promoted);
+            freed += promoted;
+        }
+
+        loop {
+            let disk_used = self.disk_used();
+            if disk_used <= new_max_disk {
+                break;
+            }
+            let batch = (disk_used - new_max_disk).min(self.cleanup_batch_size);
+
+            let items = self.database.len();
+            let (dropped, mem_freed) = self.database.cleanup_disk(cleanse_strategy, batch)?;
+
+            saturating_sub(&self.diskdb_size, dropped);
+            saturating_sub(&self.memdb_size, mem_freed);
+            self.stats
+                .record_evictions(items.saturating_sub(self.database.len()) as u64);
+            freed += dropped;
+            if dropped == 0 {
+                break;
+            }
+        }
+
+        self.stats.record_eviction(to_clean, freed);
+
+        Ok((to_clean, freed))
+    }
+
+    /// Removes every item from memory and disk.
+    /// Only the folders of cached items are deleted, other files under `cache_path` are kept.
+    pub fn clear(&mut self) -> Result<(), CacheError> {
+        for (_, v) in self.database.drain() {
+            if let Some(folder) = v.filepath.as_ref().and_then(|f| f.parent()) {
+                if folder.exists() {
+                    std::fs::remove_dir_all(folder)?;
+                }
+            }
+        }
+        std::fs::create_dir_all(&self.cache_path)?;
+
+        self.memdb_size.store(0, Ordering::Relaxed);
+        self.diskdb_size.store(0, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Removes `key` and returns the removed item, or `None` if it wasn't cached.
+    /// The value of an item on disk is read back before its files are removed,
+    /// if that fails the item is returned without a value.
+    pub fn remove_cache_item(&mut self, key: &str) -> Result<Option<DatabaseItem>, CacheError> {
+        let timer = self.stats.start_timer();
+        let removed = self.take_item(key, true);
+        self.stats.remove_timings.record(timer);
+        removed
+    }
+
+    /// Removes `key` like `remove_cache_item`, without recording the timing.
+    /// The value of an item on disk is only read back with `load_value`,
+    /// otherwise the item keeps the value it had in memory, if any.
+    fn take_item(&mut self, key: &str, load_value: bool) -> Result<Option<DatabaseItem>, CacheError> {
+        let mut removed = match self.database.del(key)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let value = if load_value {
+            match removed.load_value() {
+                Ok(v) => v,
+                Err(e) => {
+                    logger::warn(&format!("Couldn't read removed {:?}: {:?}", key, e));
+                    None
+                }
+            }
+        } else {
+            removed.value.clone()
+        };
+        self.release_item(key, &removed)?;
+        removed.value = value;
+        removed.filepath = None;
+        Ok(Some(removed))
+    }
+
+    fn remove_item(&mut self, key: &str) -> Result<Option<DatabaseItem>, CacheError> {
+        let dbi = self.database.del(key)?;
+        match dbi {
             Some(v) => {
+                self.release_item(key, &v)?;
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Subtracts the sizes of an item taken out of the database and removes its disk folder.
+    fn release_item(&self, key: &str, item: &DatabaseItem) -> Result<(), CacheError> {
+        release_item(key, item, &self.memdb_size, &self.diskdb_size)
+    }
+
+    /// Inserts an item into memory.
+    /// If memory usage then exceeds `max_ram_cache`, items are moved to disk by the
+    /// `CleanseStrategy`, and the disk and entry limits are enforced.
+    /// Values that don't fit into `max_ram_cache` at all are written to disk directly,
+    /// values larger than `max_disk_cache` are rejected with `CacheError::ValueTooLarge`.
+    /// Accepts anything convertible into a `Vec<u8>`, e.g. `&[u8]` or `String`.
+    /// Returns the item previously stored under `key`, like `remove_cache_item` would,
+    /// or `None` if there was none or the value was skipped as identical.
+    /// Unlike `remove_cache_item`, a previous value that was only on disk isn't read back.
+    pub fn insert_cache_item<V: Into<Vec<u8>>>(
+        &mut self,
+        key: String,
+        value: V,
+    ) -> Result<Option<DatabaseItem>, CacheError> {
+        self.insert_and_store(key, value.into(), Placement::Memory, None)
+    }
+
+    /// Inserts an item into `namespace`, which must be non empty and not contain `/`.
+    /// All items of a namespace can be removed at once with `evict_namespace`.
+    pub fn insert_cache_item_ns<V: Into<Vec<u8>>>(
+        &mut self,
+        namespace: &str,
+        key: &str,
+        value: V,
+    ) -> Result<Option<DatabaseItem>, CacheError> {
+        let key = namespaced_key(namespace, key)?;
+        self.insert_cache_item(key, value)
+    }
+
+    /// Like `get_cache_value`, for items inserted with `insert_cache_item_ns`.
+    pub fn get_cache_value_ns(
+        &mut self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, CacheError> {
+        let key = namespaced_key(namespace, key)?;
+        self.get_cache_value(&key)
+    }
+
+    /// Removes all items of `namespace`, in memory and on disk,
+    /// and returns the bytes freed in memory and on disk.
+    /// The disk files are removed folder by folder, along with the namespace or shard folders
+    /// they leave empty.
+    /// Items whose files can't be removed are still dropped, the error is logged.
+    pub fn evict_namespace(&mut self, namespace: &str) -> Result<u64, CacheError> {
+        let prefix = namespaced_key(namespace, "")?;
+        let cache_path = Path::new(&self.cache_path);
+        let mut mem_freed: u64 = 0;
+        let mut disk_freed: u64 = 0;
+        for (k, v) in self.database.drain_prefix(&prefix) {
+            mem_freed += v.get_mem_size() + entry_overhead(&k);
+            let (disk_size, removed) = remove_disk_files(&v);
+            disk_freed += disk_size;
+            match (removed, v.filepath.as_ref().and_then(|f| f.parent())) {
+                (Err(e), _) => {
+                    logger::error(&format!("\t\tCouldn't remove files of {:?}: {:?}", &k, e));
+                }
+                (Ok(()), Some(folder)) => remove_empty_parents(folder, cache_path),
+                (Ok(()), None) => {}
+            }
+        }
+        saturating_sub(&self.memdb_size, mem_freed);
+        saturating_sub(&self.diskdb_size, disk_freed);
+
+        Ok(mem_freed + disk_freed)
+    }
+
+    /// Removes the `count` least recently used items, in memory and on disk,
+    /// e.g. on memory pressure reported by the host application.
+    /// Returns the bytes freed in memory and on disk.
+    pub fn evict_lru(&mut self, count: usize) -> Result<u64, CacheError> {
+        let items = self.database.len();
+        let (mem_freed, disk_freed) = self
+            .database
+            .remove_entries(&CleanseStrategy::LastAccess, count);
+
+        saturating_sub(&self.memdb_size, mem_freed);
+        saturating_sub(&self.diskdb_size, disk_freed);
+        self.stats
+            .record_evictions(items.saturating_sub(self.database.len()) as u64);
+
+        Ok(mem_freed + disk_freed)
+    }
+
+    /// Inserts an item that expires once it wasn't accessed for `ttl`, instead of `decache_age`.
+    pub fn insert_cache_item_with_ttl<V: Into<Vec<u8>>>(
+        &mut self,
+        key: String,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<DatabaseItem>, CacheError> {
+        self.insert_and_store(key, value.into(), Placement::Memory, Some(ttl.as_nanos()))
+    }
+
+    /// Inserts an item that is written to disk immediately, while also staying in memory.
+    /// It counts against both the ram and the disk cache,
+    /// and doesn't need to be rewritten once it is moved out of memory.
+    pub fn insert_write_through<V: Into<Vec<u8>>>(
+        &mut self,
+        key: String,
+        value: V,
+    ) -> Result<Option<DatabaseItem>, CacheError> {
+        self.insert_and_store(key, value.into(), Placement::WriteThrough, None)
+    }
+
+    /// Inserts an item that is written to disk only, without taking up ram for its value,
+    /// e.g. for values that are written once and read rarely.
+    /// It counts against the disk cache, and only its metadata and key against the ram cache.
+    pub fn insert_cache_item_to_disk<V: Into<Vec<u8>>>(
+        &mut self,
+        key: String,
+        value: V,
+    ) -> Result<Option<DatabaseItem>, CacheError> {
+        self.insert_and_store(key, value.into(), Placement::Disk, None)
+    }
+
+    /// Inserts `value` like `insert_item`, storing it in the backing store first.
+    /// Values the cache rejects aren't stored, so the store doesn't get values the cache never had.
+    fn insert_and_store(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        placement: Placement,
+        ttl: Option<u128>,
+    ) -> Result<Option<DatabaseItem>, CacheError> {
+        self.check_value_size(&value)?;
+        self.store_in_backing_store(&key, &value)?;
+        self.insert_item(key, value, placement, ttl)
+    }
+
+    /// Stores `value` in the backing store, if `write_to_backing_store` is set.
+    fn store_in_backing_store(&self, key: &str, value: &[u8]) -> Result<(), CacheError> {
+        if !self.write_to_backing_store {
+            return Ok(());
+        }
+        if let Some(backing_store) = &self.backing_store {
+            backing_store.store(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn insert_item(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        placement: Placement,
+        ttl: Option<u128>,
+    ) -> Result<Option<DatabaseItem>, CacheError> {
+        let timer = self.stats.start_timer();
+        let value_hash = hash_bytes(&value);
+
+        let value = Arc::new(value);
+        let mut dbi = self.new_item(Arc::clone(&value), value_hash, ttl);
+        // A value that can't fit into memory goes straight to disk, instead of spilling everything else.
+        let placement = if dbi.get_mem_size() > self.max_ram_cache {
+            Placement::Disk
+        } else {
+            placement
+        };
+        if self.skip_identical(&key, &dbi, placement) {
+            return Ok(None);
+        }
+
+        let previous = self.take_item(&key, false)?;
+        if placement != Placement::Memory {
+            dbi.write_to_disk(&self.cache_path, &key, &value, self.disk_format())?;
+        }
+        if placement == Placement::Disk {
+            logger::debug(&format!("\t\tMoving {:?} straight to disk", &key));
+            dbi.value = None;
+        }
+        let mem_size = dbi.get_mem_size() + entry_overhead(&key);
+        self.memdb_size.fetch_add(mem_size, Ordering::Relaxed);
+        self.diskdb_size.fetch_add(dbi.get_disk_size()?, Ordering::Relaxed);
+        let cleanse_strategy = self.cleanse_strategy;
+        self.cleanup_entries(&cleanse_strategy, 1)?;
+        if let Some(raced) = self.database.set(key.clone(), dbi)? {
+            self.release_item(&key, &raced)?;
+        }
+        self.enforce_limits()?;
+        self.stats.record_insertion();
+        self.stats.insert_timings.record(timer);
+        Ok(previous)
+    }
+
+    /// Whether the insert of `dbi` can be skipped with `dedup_identical`, because `key` holds
+    /// the same value with the same ttl where `placement` would put it.
+    /// The stored item then counts as accessed at the time of `dbi`, like a rewrite would.
+    fn skip_identical(&self, key: &str, dbi: &DatabaseItem, placement: Placement) -> bool {
+        if !self.dedup_identical {
+            return false;
+        }
+
+        let mut skipped = false;
+        self.database.update(key, |v| {
+            let placed = match placement {
+                Placement::Memory => v.value.is_some(),
+                Placement::WriteThrough => v.value.is_some() && v.is_on_disk(),
+                Placement::Disk => v.value.is_none() && v.is_on_disk(),
+            };
+            if placed && v.value_hash == dbi.value_hash && v.ttl == dbi.ttl {
+                v.last_access = dbi.last_access;
+                skipped = true;
+            }
+        });
+        skipped
+    }
+
+    /// Rejects values bigger than `max_item_size` and values that can't be kept on disk,
+    /// as no eviction can make room for them.
+    fn check_value_size(&self, value: &[u8]) -> Result<(), CacheError> {
+        let size = value.len() as u64;
+        let max = self.max_item_size.min(self.max_disk_cache);
+        if size > max {
+            return Err(CacheError::ValueTooLarge { size, max });
+        }
+        Ok(())
+    }
+
+    /// A new in memory item, accessed now.
+    fn new_item(&self, value: Arc<Vec<u8>>, value_hash: u64, ttl: Option<u128>) -> DatabaseItem {
+        let now = self.now();
+        DatabaseItem {
+            value: Some(value),
+            last_access: now,
+            created_at: now,
+            access_counter: 0,
+            filepath: None,
+            value_hash: Some(value_hash),
+            boosted_until: None,
+            chunks: 0,
+            compressed: false,
+            ttl,
+        }
+    }
+
+    /// Inserts all `items` like `insert_cache_item`,
+    /// taking the locks of the database once instead of once per item.
+    /// Later items replace earlier ones with the same key.
+    /// Values that don't fit into `max_ram_cache` at all are written to disk directly.
+    /// Nothing is inserted if one of the values is larger than `max_disk_cache`.
+    pub fn insert_many(&mut self, items: Vec<(String, Vec<u8>)>) -> Result<(), CacheError> {
+        for (_, value) in &items {
+            self.check_value_size(value)?;
+        }
+        for (key, value) in &items {
+            self.store_in_backing_store(key, value)?;
+        }
+        // Only the last value of a key is kept, so no earlier one is written to disk for nothing.
+        let mut seen = HashSet::with_capacity(items.len());
+        let mut items: Vec<(String, Vec<u8>)> =
+            items.into_iter().rev().filter(|(k, _)| seen.insert(k.to_owned())).collect();
+        items.reverse();
+
+        let mut dbis: Vec<(String, DatabaseItem)> = Vec::with_capacity(items.len());
+        let mut mem_added: u64 = 0;
+        for (key, value) in items {
+            let value_hash = hash_bytes(&value);
+            let value = Arc::new(value);
+            let mut dbi = self.new_item(Arc::clone(&value), value_hash, None);
+            // Like in `insert_item`, a value that can't fit into memory goes straight to disk.
+            let placement = if dbi.get_mem_size() > self.max_ram_cache {
+                Placement::Disk
+            } else {
+                Placement::Memory
+            };
+            if self.skip_identical(&key, &dbi, placement) {
+                continue;
+            }
+            if placement == Placement::Disk {
+                self.take_item(&key, false)?;
+                dbi.write_to_disk(&self.cache_path, &key, &value, self.disk_format())?;
+                logger::debug(&format!("\t\tMoving {:?} straight to disk", &key));
+                dbi.value = None;
+                self.diskdb_size.fetch_add(dbi.get_disk_size()?, Ordering::Relaxed);
+            }
+            mem_added += dbi.get_mem_size() + entry_overhead(&key);
+            dbis.push((key, dbi));
+        }
+
+        let inserted = dbis.len();
+        let keys: Vec<String> = dbis.iter().map(|(k, _)| k.to_owned()).collect();
+        self.memdb_size.fetch_add(mem_added, Ordering::Relaxed);
+        for (key, previous) in keys.iter().zip(self.database.set_many(dbis)) {
+            if let Some(previous) = previous {
+                self.release_item(key, &previous)?;
+            }
+        }
+        self.enforce_limits()?;
+        for _ in 0..inserted {
+            self.stats.record_insertion();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the values of `keys` in order, like `get_cache_value` for each key,
+    /// taking the locks of the database once for all cached keys.
+    pub fn get_many(&mut self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>, CacheError> {
+        let now = self.now();
+        let decache_age = Duration::from_secs(self.decache_age).as_nanos();
+
+        let (items, expired) = self.database.touch_many(keys, now, decache_age);
+        for (key, item) in expired {
+            logger::debug(&format!("\t\tExpired {:?}", key));
+            self.release_item(&key, &item)?;
+        }
+
+        let mut values = Vec::with_capacity(keys.len());
+        for (key, item) in keys.iter().zip(items) {
+            let timer = self.stats.start_timer();
+            let value = self.resolve_value(key, item, timer)?;
+            values.push(value.map(unwrap_shared));
+        }
+
+        Ok(values)
+    }
+
+    /// Returns the item and updates its access stats.
+    /// Expired items are removed and reported as missing.
+    pub fn get_cache_item(&self, key: &str) -> Result<Option<DatabaseItem>, CacheError> {
+        self.access_item(key, |v| {
+            v.access_counter += 1;
+            v.clone()
+        })
+    }
+
+    /// Sets the last access of `key` to now, without reading its value, e.g. to keep it alive.
+    /// Doesn't count as an access in `access_counter`.
+    /// Returns whether the key exists, expired items are removed and reported as missing.
+    pub fn touch(&self, key: &str) -> bool {
+        match self.access_item(key, |_| ()) {
+            Ok(v) => v.is_some(),
+            Err(e) => {
+                logger::error(&format!("\t\tRemoving expired {:?} failed: {}", key, e));
+                false
+            }
+        }
+    }
+
+    /// Sets the last access of `key` to now and applies `f`, under a single write lock.
+    /// Expired items are removed instead.
+    fn access_item<T, F: FnOnce(&mut DatabaseItem) -> T>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<Option<T>, CacheError> {
+        access_item(
+            &self.database,
+            &self.memdb_size,
+            &self.diskdb_size,
+            key,
+            self.now(),
+            Duration::from_secs(self.decache_age).as_nanos(),
+            f,
+        )
+    }
+
+    pub fn get_cache_value(&mut self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        Ok(self.get_cache_value_shared(key)?.map(unwrap_shared))
+    }
+
+    /// Like `get_cache_value`, also returning the time the item had left until it expired,
+    /// by its ttl or `decache_age`, before this access.
+    /// Expired items are removed and reported as missing.
+    /// Values fetched from the backing store have the full `decache_age` left.
+    pub fn get_with_ttl(&mut self, key: &str) -> Result<Option<(Vec<u8>, Duration)>, CacheError> {
+        let default_ttl = Duration::from_secs(self.decache_age).as_nanos();
+        let now = self.now();
+        let remaining = self
+            .database
+            .get(key)?
+            .and_then(|v| v.remaining_ttl(now, default_ttl))
+            .unwrap_or(default_ttl);
+
+        let remaining = Duration::from_nanos(remaining.min(u128::from(u64::MAX)) as u64);
+        Ok(self.get_cache_value(key)?.map(|v| (v, remaining)))
+    }
+
+    /// Like `get_cache_value`, but hands out the stored value of in memory items
+    /// instead of a copy.
+    /// In memory hits only clone the value, not the whole item.
+    pub fn get_cache_value_shared(&mut self, key: &str) -> Result<Option<Arc<Vec<u8>>>, CacheError> {
+        let timer = self.stats.start_timer();
+        match self.access_item(key, hit)? {
+            Some(Hit::Memory(v)) => {
+                self.stats.record_hit(true);
                 logger::log("From memory");
+                self.stats.get_memory_timings.record(timer);
                 Ok(Some(v))
             }
+            Some(Hit::Disk(item)) => self.resolve_value(key, Some(item), timer),
+            None => self.resolve_value(key, None, timer),
+        }
+    }
+
+    /// Loads the value of an item returned by a get, recording the hit or miss.
+    /// Misses go to the backing store.
+    fn resolve_value(
+        &mut self,
+        key: &str,
+        cache_item: Option<DatabaseItem>,
+        timer: Option<Instant>,
+    ) -> Result<Option<Arc<Vec<u8>>>, CacheError> {
+        if cache_item.is_none() {
+            self.stats.record_miss();
+            return Ok(self.fetch_from_backing_store(key)?.map(Arc::new));
+        }
+
+        let fxi = cache_item.expect("Some is none");
+        self.stats.record_hit(fxi.value.is_some());
+        if fxi.value.is_some() {
+            logger::log("From memory");
+        } else if fxi.filepath.is_some() {
+            logger::log("From disk");
+        }
+        let value = load_value(key, &fxi)?;
+
+        if fxi.value.is_some() {
+            self.stats.get_memory_timings.record(timer);
+        } else {
+            self.stats.get_disk_timings.record(timer);
+            if let (true, Some(v)) = (self.promote_on_read, &value) {
+                self.promote_item(key.to_owned(), fxi, Arc::clone(v))?;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Like `get_cache_value`, but returns a reader over the value.
+    /// Disk values are streamed from their files instead of being loaded into memory,
+    /// in memory values are read from the stored value without copying it.
+    pub fn get_cache_value_ref(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<Box<dyn Read + Send>>, CacheError> {
+        let cache_item = self.get_cache_item(key)?;
+        let item = match cache_item {
+            Some(v) => v,
+            None => {
+                self.stats.record_miss();
+                return Ok(self
+                    .fetch_from_backing_store(key)?
+                    .map(|v| Box::new(Cursor::new(v)) as Box<dyn Read + Send>));
+            }
+        };
+
+        self.stats.record_hit(item.value.is_some());
+        if let Some(v) = item.value {
+            return Ok(Some(Box::new(Cursor::new(SharedValue(v)))));
+        }
+        if !item.is_on_disk() {
+            return Ok(None);
+        }
+        Ok(Some(item.open_disk_value()?))
+    }
+
+    /// Like `get_cache_value`, but returns only up to `len` bytes starting at `offset`,
+    /// e.g. for HTTP range requests. Ranges reaching past the end of the value are clamped to it,
+    /// an `offset` past the end returns an empty value.
+    /// Disk values are read from `offset` without loading the whole value.
+    pub fn get_range(
+        &mut self,
+        key: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, CacheError> {
+        let slice = |v: &[u8]| {
+            let start = offset.min(v.len() as u64) as usize;
+            let end = offset.saturating_add(len).min(v.len() as u64) as usize;
+            v[start..end].to_vec()
+        };
+
+        let item = match self.get_cache_item(key)? {
+            Some(v) => v,
+            None => {
+                self.stats.record_miss();
+                return Ok(self.fetch_from_backing_store(key)?.map(|v| slice(&v)));
+            }
+        };
+
+        self.stats.record_hit(item.value.is_some());
+        if let Some(v) = item.value {
+            return Ok(Some(slice(&v)));
+        }
+        if !item.is_on_disk() {
+            return Ok(None);
+        }
+        Ok(Some(item.read_disk_range(offset, len)?))
+    }
+
+    /// Moves the value of a disk item into memory, if it fits into `max_ram_cache`.
+    fn promote_item(
+        &mut self,
+        key: String,
+        mut item: DatabaseItem,
+        value: Arc<Vec<u8>>,
+    ) -> Result<(), CacheError> {
+        let mem_size = item.get_mem_size();
+        let disk_size = item.get_disk_size()?;
+        item.value = Some(value);
+        let mem_added = item.get_mem_size() - mem_size;
+        if self.memory_used() + mem_added > self.max_ram_cache {
+            return Ok(());
+        }
+
+        if let Some(folder) = item.filepath.take().as_ref().and_then(|v| v.parent()) {
+            if folder.exists() {
+                std::fs::remove_dir_all(folder)?;
+            }
+        }
+        item.chunks = 0;
+        item.compressed = false;
+
+        logger::debug(&format!("\t\tPromoting {:?} to memory", &key));
+        self.database.set(key, item)?;
+        self.memdb_size.fetch_add(mem_added, Ordering::Relaxed);
+        saturating_sub(&self.diskdb_size, disk_size);
+
+        Ok(())
+    }
+
+    fn fetch_from_backing_store(&mut self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        let backing_store = match &self.backing_store {
+            Some(v) => Arc::clone(v),
+            None => return Ok(None),
+        };
+
+        match backing_store.fetch(key)? {
+            Some(value) => {
+                logger::log("From backing store");
+                self.check_value_size(&value)?;
+                self.insert_item(key.to_owned(), value.clone(), Placement::Memory, None)?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the value of `key`, computing and inserting it with `f` if it isn't cached.
+    /// The cache is borrowed mutably from the lookup to the insert,
+    /// so callers sharing it behind a lock never run `f` twice for the same key.
+    pub fn get_or_insert_with<F: FnOnce() -> Vec<u8>>(
+        &mut self,
+        key: String,
+        f: F,
+    ) -> Result<Vec<u8>, CacheError> {
+        if let Some(v) = self.get_cache_value(&key)? {
+            return Ok(v);
+        }
+
+        let value = f();
+        self.insert_cache_item(key, value.clone())?;
+        Ok(value)
+    }
+
+    /// Returns the values of `keys` in order.
+    /// `compute` is called once, with only the keys that aren't cached,
+    /// and must return their values in the same order. These are inserted into the cache.
+    pub fn get_or_compute_many<F: FnOnce(&[String]) -> Vec<Vec<u8>>>(
+        &mut self,
+        keys: Vec<String>,
+        compute: F,
+    ) -> Result<Vec<Vec<u8>>, CacheError> {
+        let mut values: Vec<Option<Vec<u8>>> = Vec::with_capacity(keys.len());
+        let mut missing: Vec<String> = vec![];
+
+        for k in &keys {
+            let value = self.get_cache_value(k)?;
+            if value.is_none() {
+                missing.push(k.to_owned());
+            }
+            values.push(value);
         }
+
+        if missing.is_empty() {
+            return Ok(values.into_iter().flatten().collect());
+        }
+
+        let computed = compute(&missing);
+        if computed.len() != missing.len() {
+            return Err(CacheError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "compute returned {} values for {} keys",
+                    computed.len(),
+                    missing.len()
+                ),
+            )));
+        }
+
+        let mut computed = computed.into_iter();
+        for (k, value) in keys.into_iter().zip(values.iter_mut()) {
+            if value.is_none() {
+                let v = computed.next().expect("computed values ran out");
+                self.insert_cache_item(k, v.clone())?;
+                *value = Some(v);
+            }
+        }
+
+        Ok(values.into_iter().flatten().collect())
+    }
+
+    /// Reads a value from memory or disk like `get_cache_value`,
+    /// without changing its access stats or consulting the backing store.
+    /// Expired items are reported as missing, but not removed.
+    pub fn peek(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        let default_ttl = Duration::from_secs(self.decache_age).as_nanos();
+        match self.database.get(key)? {
+            Some(item) if !item.is_expired(self.now(), default_ttl) => {
+                Ok(load_value(key, &item)?.map(unwrap_shared))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the value and the metadata of an item.
+    /// Both are taken under the same lock, and the access stats are updated once.
+    /// Expired items are removed and reported as missing, the hit or miss is recorded
+    /// like in `get_cache_value`.
+    pub fn get_full(&self, key: &str) -> Result<Option<(Vec<u8>, ItemMetadata)>, CacheError> {
+        let timer = self.stats.start_timer();
+        let item = match self.get_cache_item(key)? {
+            Some(v) => v,
+            None => {
+                self.stats.record_miss();
+                return Ok(None);
+            }
+        };
+
+        self.stats.record_hit(item.value.is_some());
+        let value = load_value(key, &item)?;
+        if item.value.is_some() {
+            self.stats.get_memory_timings.record(timer);
+        } else {
+            self.stats.get_disk_timings.record(timer);
+        }
+        Ok(value.map(|value| (unwrap_shared(value), ItemMetadata::from(&item))))
+    }
+
+    /// Replaces every value with `f(key, value)`, keeping items where they are (memory or disk).
+    /// Items are processed one at a time, so disk values are never all loaded at once.
+    pub fn remap_values<F: Fn(&str, Vec<u8>) -> Vec<u8>>(&mut self, f: F) -> Result<(), CacheError> {
+        for key in self.database.keys() {
+            let mut item = match self.database.get(&key)? {
+                Some(v) => v,
+                None => continue,
+            };
+            let old_mem_size = item.get_mem_size();
+            let old_disk_size = item.get_disk_size()?;
+
+            let in_memory = item.value.is_some();
+            let value = match item.value.take() {
+                Some(v) => unwrap_shared(v),
+                None => match load_value(&key, &item)? {
+                    Some(v) => unwrap_shared(v),
+                    None => continue,
+                },
+            };
+
+            let new_value = f(&key, value);
+            item.value_hash = Some(hash_bytes(&new_value));
+            if item.filepath.is_some() {
+                item.write_to_disk(&self.cache_path, &key, &new_value, self.disk_format())?;
+            }
+            if in_memory {
+                item.value = Some(Arc::new(new_value));
+            }
+
+            self.memdb_size.fetch_add(item.get_mem_size(), Ordering::Relaxed);
+            saturating_sub(&self.memdb_size, old_mem_size);
+            self.diskdb_size.fetch_add(item.get_disk_size()?, Ordering::Relaxed);
+            saturating_sub(&self.diskdb_size, old_disk_size);
+            self.database.set(key, item)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks every disk file against the hash of the value it was written from.
+    /// Uncompressed files are streamed, and all are checked in parallel on the management threadpool.
+    /// Items without a stored hash are skipped.
+    pub fn verify_all(&self) -> Result<VerifyReport, CacheError> {
+        let disk_items = self.database.disk_items();
+
+        let results: Vec<(String, Option<bool>)> = self.run_management(|| {
+            disk_items
+                .into_par_iter()
+                .filter_map(|(k, item)| {
+                    let hash = item.value_hash?;
+                    if !item.is_on_disk() {
+                        return Some((k, None));
+                    }
+                    let disk_hash = if item.compressed {
+                        item.read_disk_value().map(|v| hash_bytes(&v))
+                    } else {
+                        hash_files(&item.disk_files())
+                    };
+                    match disk_hash {
+                        Ok(v) => Some((k, Some(v == hash))),
+                        Err(e) => {
+                            logger::error(&format!("\tCouldn't read {:?}: {:?}", item.filepath, e));
+                            Some((k, Some(false)))
+                        }
+                    }
+                })
+                .collect()
+        });
+
+        let mut report = VerifyReport::default();
+        for (k, result) in results {
+            match result {
+                Some(true) => report.ok.push(k),
+                Some(false) => report.corrupt.push(k),
+                None => report.missing.push(k),
+            }
+        }
+        report.ok.sort();
+        report.corrupt.sort();
+        report.missing.sort();
+
+        Ok(report)
+    }
+
+    /// Removes the corrupt and missing items of a `verify_all` report.
+    pub fn evict_failed(&mut self, report: &VerifyReport) -> Result<(), CacheError> {
+        for k in report.corrupt.iter().chain(report.missing.iter()) {
+            self.remove_item(k)?;
+        }
+        Ok(())
+    }
+
+    /// Copies every entry into `dest`, returning the number of entries copied.
+    /// Entries are taken from a snapshot and copied one at a time, so at most
+    /// one disk-resident value is buffered at once.
+    /// `dest` spills or drops items as usual to stay within its own limits.
+    pub fn replicate_to(&self, dest: &mut Cache) -> Result<u64, CacheError> {
+        let mut copied: u64 = 0;
+
+        for (k, v) in self.database.snapshot() {
+            match load_value(&k, &v)? {
+                Some(value) => {
+                    dest.insert_cache_item(k, unwrap_shared(value))?;
+                    copied += 1;
+                }
+                None => {
+                    logger::warn(&format!("\tSkipping {:?}: value is gone", k));
+                }
+            }
+        }
+
+        Ok(copied)
+    }
+
+    fn enforce_limits(&mut self) -> Result<(), CacheError> {
+        let cleanse_strategy = self.cleanse_strategy;
+        self.cleanup_mem_cache(&cleanse_strategy, self.max_ram_cache)?;
+        self.cleanup_disk_cache(&cleanse_strategy, self.max_disk_cache)?;
+        self.cleanup_entries(&cleanse_strategy, 0)?;
+        Ok(())
     }
 }
@@ -0,0 +1,50 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors returned by `Cache`.
+/// * `Io` : Reading or writing the disk cache failed.
+/// * `ValueTooLarge` : The value can't be cached within the configured limits.
+/// * `KeyInvalid` : The key can't be used to store a value.
+/// * `Poisoned` : Shared cache state was left inconsistent by a panicking thread.
+/// * `Corrupt` : The disk value of `key` can't be decoded or doesn't match its checksum.
+/// * `InvalidConfig` : A setting passed to `CacheBuilder` can't be used.
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    ValueTooLarge { size: u64, max: u64 },
+    KeyInvalid(String),
+    Poisoned,
+    Corrupt { key: String },
+    InvalidConfig(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Io(e) => write!(f, "io error: {}", e),
+            CacheError::ValueTooLarge { size, max } => {
+                write!(f, "value of {} bytes exceeds the maximum of {} bytes", size, max)
+            }
+            CacheError::KeyInvalid(key) => write!(f, "invalid key: {:?}", key),
+            CacheError::Poisoned => write!(f, "cache state poisoned"),
+            CacheError::Corrupt { key } => write!(f, "corrupt disk value for key {:?}", key),
+            CacheError::InvalidConfig(e) => write!(f, "invalid configuration: {}", e),
+        }
+    }
+}
+
+impl Error for CacheError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CacheError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        CacheError::Io(e)
+    }
+}
@@ -1 +1,7 @@
+#[cfg(feature = "async")]
+pub mod async_cache;
+pub mod backing_store;
 pub mod cache;
+pub mod error;
+pub mod stats;
+pub mod typed_cache;
@@ -0,0 +1,232 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counters collected by a `Cache`.
+/// * `eviction_shortfall_bytes` : Bytes the last cleanup couldn't free.
+/// * `hits` : Gets that found the key, `mem_hits` + `disk_hits`.
+/// * `misses` : Gets that didn't find the key, even if the backing store had it.
+/// * `evictions` : Items dropped by disk cleanups and the entry limit.
+/// * `insertions` : Values inserted, not counting skipped identical values.
+/// * `clock_reads` : Reads of the system clock for access times, see `TimeResolution`.
+/// * `instrument` : Whether the per operation timings are recorded. [Default: false]
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    eviction_shortfall_bytes: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    mem_hits: AtomicU64,
+    disk_hits: AtomicU64,
+    evictions: AtomicU64,
+    insertions: AtomicU64,
+    clock_reads: AtomicU64,
+    instrument: AtomicBool,
+    pub get_memory_timings: OpTimings,
+    pub get_disk_timings: OpTimings,
+    pub insert_timings: OpTimings,
+    pub remove_timings: OpTimings,
+    pub resize_timings: OpTimings,
+}
+
+/// Point in time copy of `CacheStats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStatsSnapshot {
+    pub eviction_shortfall_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub mem_hits: u64,
+    pub disk_hits: u64,
+    pub evictions: u64,
+    pub insertions: u64,
+    pub clock_reads: u64,
+    pub get_memory_timings: OpTimingsSnapshot,
+    pub get_disk_timings: OpTimingsSnapshot,
+    pub insert_timings: OpTimingsSnapshot,
+    pub remove_timings: OpTimingsSnapshot,
+    pub resize_timings: OpTimingsSnapshot,
+    pub lock_contention: LockContentionSnapshot,
+}
+
+/// Usage of a `Cache`, see `Cache::memory_report`.
+/// * `mem_bytes` : Bytes used in memory, like `Cache::memory_used`.
+/// * `disk_bytes` : Bytes used on disk.
+/// * `item_count` : Items in memory and on disk.
+/// * `disk_item_count` : Items whose value is only on disk.
+/// * `mem_utilization` : `mem_bytes` relative to the ram limit.
+/// * `disk_utilization` : `disk_bytes` relative to the disk limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MemoryReport {
+    pub mem_bytes: u64,
+    pub disk_bytes: u64,
+    pub item_count: usize,
+    pub disk_item_count: usize,
+    pub mem_utilization: f64,
+    pub disk_utilization: f64,
+}
+
+/// Time spent waiting for the database lock, only counting acquisitions that had to wait.
+#[derive(Debug, Default)]
+pub struct LockContention {
+    enabled: AtomicBool,
+    read_waits: AtomicU64,
+    read_wait_nanos: AtomicU64,
+    write_waits: AtomicU64,
+    write_wait_nanos: AtomicU64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LockContentionSnapshot {
+    pub read_waits: u64,
+    pub read_wait_nanos: u64,
+    pub write_waits: u64,
+    pub write_wait_nanos: u64,
+}
+
+/// Durations of one kind of operation, in nanoseconds.
+#[derive(Debug)]
+pub struct OpTimings {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    min_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpTimingsSnapshot {
+    pub count: u64,
+    pub min_nanos: u64,
+    pub max_nanos: u64,
+    pub avg_nanos: u64,
+}
+
+impl Default for OpTimings {
+    fn default() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            total_nanos: AtomicU64::new(0),
+            min_nanos: AtomicU64::new(u64::MAX),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl OpTimings {
+    /// Records the time elapsed since `started`, if timing was started.
+    pub fn record(&self, started: Option<Instant>) {
+        if let Some(started) = started {
+            let nanos = started.elapsed().as_nanos() as u64;
+            self.count.fetch_add(1, Ordering::Relaxed);
+            self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+            self.min_nanos.fetch_min(nanos, Ordering::Relaxed);
+            self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> OpTimingsSnapshot {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return OpTimingsSnapshot::default();
+        }
+
+        OpTimingsSnapshot {
+            count,
+            min_nanos: self.min_nanos.load(Ordering::Relaxed),
+            max_nanos: self.max_nanos.load(Ordering::Relaxed),
+            avg_nanos: self.total_nanos.load(Ordering::Relaxed) / count,
+        }
+    }
+}
+
+impl LockContention {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn record_read_wait(&self, waited: Duration) {
+        self.read_waits.fetch_add(1, Ordering::Relaxed);
+        self.read_wait_nanos
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_write_wait(&self, waited: Duration) {
+        self.write_waits.fetch_add(1, Ordering::Relaxed);
+        self.write_wait_nanos
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LockContentionSnapshot {
+        LockContentionSnapshot {
+            read_waits: self.read_waits.load(Ordering::Relaxed),
+            read_wait_nanos: self.read_wait_nanos.load(Ordering::Relaxed),
+            write_waits: self.write_waits.load(Ordering::Relaxed),
+            write_wait_nanos: self.write_wait_nanos.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl CacheStats {
+    pub fn record_eviction(&self, requested: u64, freed: u64) {
+        self.eviction_shortfall_bytes
+            .store(requested.saturating_sub(freed), Ordering::Relaxed);
+    }
+
+    pub fn record_hit(&self, in_memory: bool) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        if in_memory {
+            self.mem_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.disk_hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_evictions(&self, items: u64) {
+        self.evictions.fetch_add(items, Ordering::Relaxed);
+    }
+
+    pub fn record_insertion(&self) {
+        self.insertions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_clock_read(&self) {
+        self.clock_reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_instrument(&self, instrument: bool) {
+        self.instrument.store(instrument, Ordering::Relaxed);
+    }
+
+    /// Starts timing an operation, if instrumentation is enabled.
+    pub fn start_timer(&self) -> Option<Instant> {
+        if self.instrument.load(Ordering::Relaxed) {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    pub fn snapshot(&self) -> CacheStatsSnapshot {
+        CacheStatsSnapshot {
+            eviction_shortfall_bytes: self.eviction_shortfall_bytes.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            mem_hits: self.mem_hits.load(Ordering::Relaxed),
+            disk_hits: self.disk_hits.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            clock_reads: self.clock_reads.load(Ordering::Relaxed),
+            get_memory_timings: self.get_memory_timings.snapshot(),
+            get_disk_timings: self.get_disk_timings.snapshot(),
+            insert_timings: self.insert_timings.snapshot(),
+            remove_timings: self.remove_timings.snapshot(),
+            resize_timings: self.resize_timings.snapshot(),
+            lock_contention: LockContentionSnapshot::default(),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::cache_service::cache::Cache;
+use crate::cache_service::error::CacheError;
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::io;
+use std::marker::PhantomData;
+
+/// Converts values of type `T` to and from the bytes stored in a `Cache`, see `TypedCache`.
+pub trait Codec<T> {
+    fn encode(&self, value: &T) -> io::Result<Vec<u8>>;
+
+    /// Returns an error of kind `InvalidData` if `bytes` don't hold a `T`.
+    fn decode(&self, bytes: &[u8]) -> io::Result<T>;
+}
+
+/// Encodes values as JSON with `serde_json`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(&self, value: &T) -> io::Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(io::Error::from)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<T> {
+        serde_json::from_slice(bytes).map_err(io::Error::from)
+    }
+}
+
+/// A `Cache` holding values of type `T`, encoded with the codec `C`.
+/// The bytes are stored like any other value, so all settings of the cache apply.
+#[derive(Debug)]
+pub struct TypedCache<T, C> {
+    cache: Cache,
+    codec: C,
+    values: PhantomData<fn() -> T>,
+}
+
+impl<T, C: Codec<T>> TypedCache<T, C> {
+    pub fn new(cache: Cache, codec: C) -> Self {
+        Self {
+            cache,
+            codec,
+            values: PhantomData,
+        }
+    }
+
+    /// Encodes `value` and inserts it like `Cache::insert_cache_item`.
+    pub fn insert(&mut self, key: String, value: &T) -> Result<(), CacheError> {
+        let bytes = self.codec.encode(value)?;
+        self.cache.insert_cache_item(key, bytes)?;
+        Ok(())
+    }
+
+    /// Gets the value of `key` like `Cache::get_cache_value` and decodes it.
+    pub fn get(&mut self, key: &str) -> Result<Option<T>, CacheError> {
+        match self.cache.get_cache_value_shared(key)? {
+            Some(v) => Ok(Some(self.codec.decode(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The underlying cache, e.g. for changing its settings or removing items.
+    pub fn cache(&mut self) -> &mut Cache {
+        &mut self.cache
+    }
+
+    pub fn into_inner(self) -> Cache {
+        self.cache
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + DeserializeOwned> TypedCache<T, JsonCodec> {
+    /// A typed cache storing its values as JSON.
+    pub fn json(cache: Cache) -> Self {
+        Self::new(cache, JsonCodec)
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+/// Keys ordered by access time, least recently used first.
+/// A doubly linked list stored in a `Vec`, so moving a key to the most recently used end
+/// and reading the least recently used one are O(1).
+#[derive(Debug)]
+pub struct LruIndex<S> {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    slots: HashMap<String, usize, S>,
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+/// Bytes a key takes in the index besides a copy of its bytes: its node, its slot and control byte.
+pub const ENTRY_OVERHEAD: u64 =
+    (std::mem::size_of::<Node>() + std::mem::size_of::<(String, usize)>() + 1) as u64;
+
+#[derive(Debug)]
+struct Node {
+    key: String,
+    last_access: u128,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl<S: BuildHasher> LruIndex<S> {
+    /// Creates an empty index with room for `capacity` keys, hashing them with `hasher`.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self {
+            nodes: Vec::with_capacity(capacity),
+            free: vec![],
+            slots: HashMap::with_capacity_and_hasher(capacity, hasher),
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// Adds `key` or moves it to its place for `last_access`.
+    /// Keys accessed now go to the most recently used end in O(1),
+    /// older access times walk back from there until they fit.
+    pub fn touch(&mut self, key: &str, last_access: u128) {
+        if let Some(&idx) = self.slots.get(key) {
+            if self.nodes[idx].last_access == last_access {
+                return;
+            }
+            self.unlink(idx);
+            self.nodes[idx].last_access = last_access;
+            self.link_sorted(idx);
+            return;
+        }
+
+        let node = Node {
+            key: key.to_owned(),
+            last_access,
+            prev: None,
+            next: None,
+        };
+        let idx = match self.free.pop() {
+            Some(v) => {
+                self.nodes[v] = node;
+                v
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.slots.insert(key.to_owned(), idx);
+        self.link_sorted(idx);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if let Some(idx) = self.slots.remove(key) {
+            self.unlink(idx);
+            self.nodes[idx].key = String::new();
+            self.free.push(idx);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.slots.clear();
+        self.head = None;
+        self.tail = None;
+    }
+
+    /// Keys from least to most recently used.
+    pub fn iter(&self) -> impl Iterator<Item = &str> + '_ {
+        let mut next = self.head;
+        std::iter::from_fn(move || {
+            let node = &self.nodes[next?];
+            next = node.next;
+            Some(node.key.as_str())
+        })
+    }
+
+    /// Bytes allocated by the index beyond `ENTRY_OVERHEAD` and the keys of its entries:
+    /// unused nodes and slots, and the free list.
+    pub fn unused_bytes(&self) -> u64 {
+        let node_size = std::mem::size_of::<Node>() as u64;
+        let slot_size = std::mem::size_of::<(String, usize)>() as u64;
+        let used = self.slots.len() as u64;
+
+        (self.nodes.capacity() as u64 - used) * node_size
+            + self.free.capacity() as u64 * std::mem::size_of::<usize>() as u64
+            + (self.slots.capacity() as u64 - used) * (slot_size + 1)
+    }
+
+    fn unlink(&mut self, idx: usize) {
+        let (prev, next) = (self.nodes[idx].prev, self.nodes[idx].next);
+        match prev {
+            Some(p) => self.nodes[p].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(n) => self.nodes[n].prev = prev,
+            None => self.tail = prev,
+        }
+        self.nodes[idx].prev = None;
+        self.nodes[idx].next = None;
+    }
+
+    /// Links `idx` behind the last node that wasn't accessed after it.
+    fn link_sorted(&mut self, idx: usize) {
+        let last_access = self.nodes[idx].last_access;
+        let mut prev = self.tail;
+        while let Some(p) = prev {
+            if self.nodes[p].last_access <= last_access {
+                break;
+            }
+            prev = self.nodes[p].prev;
+        }
+
+        let next = match prev {
+            Some(p) => self.nodes[p].next,
+            None => self.head,
+        };
+        self.nodes[idx].prev = prev;
+        self.nodes[idx].next = next;
+        match prev {
+            Some(p) => self.nodes[p].next = Some(idx),
+            None => self.head = Some(idx),
+        }
+        match next {
+            Some(n) => self.nodes[n].prev = Some(idx),
+            None => self.tail = Some(idx),
+        }
+    }
+}
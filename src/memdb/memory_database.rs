@@ -1,59 +1,288 @@
-use crate::cache_service::cache::CleanseStrategy;
+use crate::cache_service::cache::{CleanseStrategy, Compression, DiskLayout};
+use crate::cache_service::stats::{LockContention, LockContentionSnapshot};
+use crate::memdb::lru::{self, LruIndex};
 use crate::tools;
-use crate::tools::{fmt_bytes, get_nano_time, logger, nano_time_fmt, get_non_buffered_file_handle};
+use crate::tools::{
+    fmt_bytes, get_nano_time, hash_bytes, logger, nano_time_fmt, read_non_buffered, write_atomic,
+};
 use parking_lot::{lock_api, RwLock};
+use rayon::prelude::*;
+use rayon::ThreadPool;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Formatter;
-use std::fs::{create_dir_all, remove_dir_all};
-use std::hash::BuildHasherDefault;
-use std::io::Write;
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::hash::{BuildHasher, BuildHasherDefault};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use std::{fs, io};
 use twox_hash::XxHash64;
 
-
-
+/// With the `serde` feature items can be serialized, the value as a sequence of bytes.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DatabaseItem {
-    pub value: Option<Vec<u8>>,
+    pub value: Option<Arc<Vec<u8>>>,
     pub last_access: u128,
+    pub created_at: u128,
     pub access_counter: u64,
     pub filepath: Option<PathBuf>,
+    pub value_hash: Option<u64>,
+    pub boosted_until: Option<u128>,
+    pub chunks: u32,
+    pub compressed: bool,
+    /// Nanoseconds since the last access after which the item expires,
+    /// overriding the cache wide `decache_age`.
+    pub ttl: Option<u128>,
+}
+
+/// Estimated bytes an entry takes in a `FastDB` besides the bytes of its key:
+/// the `(String, DatabaseItem)` bucket of the map with its control byte, which holds the key
+/// and all fields of the item inline, and its place in the LRU index.
+/// `DatabaseItem::get_mem_size` only adds what the item allocates beyond its bucket, the value.
+/// Unused buckets of the map aren't counted, see `FastDB::index_overhead_bytes` for those.
+pub const ENTRY_OVERHEAD: u64 =
+    (std::mem::size_of::<(String, DatabaseItem)>() + 1) as u64 + lru::ENTRY_OVERHEAD;
+
+/// Bytes of the `Arc` allocation of a value besides the value: its two counters and the `Vec`.
+const VALUE_ALLOCATION_OVERHEAD: usize =
+    2 * std::mem::size_of::<usize>() + std::mem::size_of::<Vec<u8>>();
+
+/// Estimated bytes `key` costs besides its item: `ENTRY_OVERHEAD` and three copies of the key,
+/// one in the map and two in the LRU index, in its slot and its node.
+pub fn entry_overhead(key: &str) -> u64 {
+    3 * key.len() as u64 + ENTRY_OVERHEAD
 }
+
+/// How values are written to disk.
+/// * `max_chunk_size` : Split values bigger than this into several files.
+/// * `compression` : Codec used to compress the files.
+/// * `layout` : Folder the files of a key are written to.
+#[derive(Debug, Clone, Copy)]
+pub struct DiskFormat {
+    pub max_chunk_size: Option<u64>,
+    pub compression: Compression,
+    pub layout: DiskLayout,
+}
+
+impl Default for DiskFormat {
+    fn default() -> Self {
+        Self {
+            max_chunk_size: None,
+            compression: Compression::None,
+            layout: DiskLayout::Flat,
+        }
+    }
+}
+
 impl DatabaseItem {
+    /// Creates an in memory item holding `value`.
+    pub fn new(value: Vec<u8>) -> Self {
+        Self {
+            value: Some(Arc::new(value)),
+            ..Self::default()
+        }
+    }
+
+    /// Bytes allocated for the value: the bytes and the `Arc` holding them, 0 if it is on disk.
     pub fn get_value_mem_size(&self) -> u64 {
-        let val_len = match &self.value {
-            Some(v) => v.len(),
-            _ => 0,
-        } as u64;
-        let opt_vec = std::mem::size_of_val::<Option<Vec<u8>>>(&self.value) as u64;
-        (std::mem::size_of::<u8>() as u64 * val_len) + opt_vec
+        match &self.value {
+            Some(v) => (v.len() + VALUE_ALLOCATION_OVERHEAD) as u64,
+            None => 0,
+        }
     }
 
+    /// Bytes the item allocates beyond its bucket in the map, see `ENTRY_OVERHEAD`.
+    /// The path buffer of a disk item isn't counted, so moving to or from disk only changes the value.
     pub fn get_mem_size(&self) -> u64 {
         self.get_value_mem_size()
-            + std::mem::size_of::<u64>() as u64
-            + std::mem::size_of::<u128>() as u64
-            + std::mem::size_of_val::<Option<PathBuf>>(&self.filepath) as u64
     }
+
     pub fn get_disk_size(&self) -> io::Result<u64> {
+        let mut size: u64 = 0;
+        for v in self.disk_files() {
+            if v.exists() {
+                size += fs::metadata(v)?.len();
+            }
+        }
+        Ok(size)
+    }
+
+    /// Files holding the value on disk: the cachefile, or its chunks if it was split.
+    pub fn disk_files(&self) -> Vec<PathBuf> {
         match &self.filepath {
-            Some(v) => {
-                if v.exists() {
-                    Ok(fs::metadata(v)?.len())
-                } else {
-                    Ok(0)
-                }
+            Some(v) if self.chunks > 0 => (0..self.chunks).map(|i| chunk_path(v, i)).collect(),
+            Some(v) => vec![v.to_owned()],
+            None => vec![],
+        }
+    }
+
+    /// Whether all files of the value exist on disk.
+    pub fn is_on_disk(&self) -> bool {
+        let files = self.disk_files();
+        !files.is_empty() && files.iter().all(|v| v.exists())
+    }
+
+    /// Returns the value, reading it from disk if it was moved there.
+    /// In memory values are shared, not copied.
+    /// Disk values are checked against `value_hash`, a mismatch is an `InvalidData` error.
+    pub fn load_value(&self) -> io::Result<Option<Arc<Vec<u8>>>> {
+        if let Some(v) = &self.value {
+            return Ok(Some(Arc::clone(v)));
+        }
+        if !self.is_on_disk() {
+            return Ok(None);
+        }
+
+        let value = self.read_disk_value()?;
+        if let Some(hash) = self.value_hash {
+            if hash_bytes(&value) != hash {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Checksum mismatch",
+                ));
+            }
+        }
+        Ok(Some(Arc::new(value)))
+    }
+
+    /// Reads and, if needed, decompresses the disk files of the value.
+    /// The files are read non buffered, so reads don't fill the page cache either.
+    pub fn read_disk_value(&self) -> io::Result<Vec<u8>> {
+        let mut buff: Vec<u8> = vec![];
+        for v in self.disk_files() {
+            let data = read_non_buffered(v)?;
+            if buff.is_empty() {
+                buff = data;
+            } else {
+                buff.extend_from_slice(&data);
             }
-            None => Ok(0),
+        }
+
+        if self.compressed {
+            decompress(&buff)
+        } else {
+            Ok(buff)
         }
     }
 
+    /// Reads up to `len` bytes of the disk value starting at `offset`, fewer if the value ends before.
+    /// Uncompressed files are seeked into, compressed ones are decompressed up to `offset` and skipped.
+    pub fn read_disk_range(&self, mut offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut buff: Vec<u8> = vec![];
+        if self.compressed {
+            let mut reader = self.open_disk_value()?;
+            io::copy(&mut reader.by_ref().take(offset), &mut io::sink())?;
+            reader.take(len).read_to_end(&mut buff)?;
+            return Ok(buff);
+        }
+
+        for v in self.disk_files() {
+            let left = len - buff.len() as u64;
+            if left == 0 {
+                break;
+            }
+            let file_len = fs::metadata(&v)?.len();
+            if offset >= file_len {
+                offset -= file_len;
+                continue;
+            }
+            let mut file = File::open(v)?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.take(left).read_to_end(&mut buff)?;
+            offset = 0;
+        }
+        Ok(buff)
+    }
+
+    /// Opens the disk files of the value as one reader, decompressing while reading.
+    /// Only lz4 values are read into memory first, as they aren't written in a streaming format.
+    /// Unlike `load_value`, the value isn't checked against `value_hash`.
+    pub fn open_disk_value(&self) -> io::Result<Box<dyn Read + Send>> {
+        let mut reader: Box<dyn Read + Send> = Box::new(io::empty());
+        for v in self.disk_files() {
+            reader = Box::new(reader.chain(File::open(v)?));
+        }
+        if !self.compressed {
+            return Ok(reader);
+        }
+
+        let mut header = [0u8; 1];
+        reader.read_exact(&mut header)?;
+        match header[0] {
+            ZSTD_HEADER => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+            LZ4_HEADER => {
+                let mut buff = vec![];
+                reader.read_to_end(&mut buff)?;
+                let value = lz4_flex::decompress_size_prepended(&buff)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                Ok(Box::new(Cursor::new(value)))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unknown compression header",
+            )),
+        }
+    }
+
+    /// Writes `value` to the cachefile of `key` and points the item at it.
+    pub fn write_to_disk(
+        &mut self,
+        cache_path: &str,
+        key: &str,
+        value: &[u8],
+        format: DiskFormat,
+    ) -> io::Result<()> {
+        let (file_path, chunks) = write_cachefile(cache_path, key, value, format)?;
+        self.filepath = Some(file_path);
+        self.chunks = chunks;
+        self.compressed = format.compression != Compression::None;
+        Ok(())
+    }
+
+    /// Recreates the item of a cachefile folder written by `write_cachefile`, e.g. after losing the index.
+    /// Whether the files are `compressed` can't be told from them, so it has to be given.
+    /// The item counts as accessed now and has no `value_hash`.
+    /// Returns `None` if `folder` holds no cachefile.
+    pub fn from_disk(folder: &Path, compressed: bool) -> Option<Self> {
+        let file_path = folder.join("cachefile");
+        let mut chunks: u32 = 0;
+        while chunk_path(&file_path, chunks).is_file() {
+            chunks += 1;
+        }
+        if chunks == 0 && !file_path.is_file() {
+            return None;
+        }
+
+        Some(Self {
+            filepath: Some(file_path),
+            chunks,
+            compressed,
+            ..Self::default()
+        })
+    }
+
+    /// Whether the item wasn't accessed for longer than its `ttl`, or `default_ttl` if it has none.
+    pub fn is_expired(&self, now: u128, default_ttl: u128) -> bool {
+        now.saturating_sub(self.last_access) > self.ttl.unwrap_or(default_ttl)
+    }
+
+    /// Nanoseconds left until the item expires, `None` if it already is, see `is_expired`.
+    pub fn remaining_ttl(&self, now: u128, default_ttl: u128) -> Option<u128> {
+        let elapsed = now.saturating_sub(self.last_access);
+        self.ttl.unwrap_or(default_ttl).checked_sub(elapsed)
+    }
+
     fn get_display(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "filepath: {}, last_access: {}, access_counter: {}, value: {}, value_mem_size {}, mem_size: {}, disk_size: {}",
+        write!(f, "filepath: {}, created_at: {}, last_access: {}, access_counter: {}, value: {}, value_mem_size {}, mem_size: {}, disk_size: {}",
                format!("{:?}", self.filepath),
+               nano_time_fmt(self.created_at),
                nano_time_fmt(self.last_access),
                self.access_counter,
                format!("{:?}", self.value),
@@ -66,11 +295,18 @@ impl DatabaseItem {
 
 impl Default for DatabaseItem {
     fn default() -> Self {
+        let now = get_nano_time();
         Self {
             value: None,
-            last_access: get_nano_time(),
+            last_access: now,
+            created_at: now,
             access_counter: 0,
             filepath: None,
+            value_hash: None,
+            boosted_until: None,
+            chunks: 0,
+            compressed: false,
+            ttl: None,
         }
     }
 }
@@ -87,67 +323,565 @@ impl fmt::Debug for DatabaseItem {
     }
 }
 
+/// Access statistics and location of an item, without its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemMetadata {
+    pub created_at: u128,
+    pub last_access: u128,
+    pub access_counter: u64,
+    pub in_memory: bool,
+    pub filepath: Option<PathBuf>,
+}
+
+impl From<&DatabaseItem> for ItemMetadata {
+    fn from(item: &DatabaseItem) -> Self {
+        Self {
+            created_at: item.created_at,
+            last_access: item.last_access,
+            access_counter: item.access_counter,
+            in_memory: item.value.is_some(),
+            filepath: item.filepath.clone(),
+        }
+    }
+}
+
+/// (key, access_counter, last_access, mem_size, disk_size, created_at, boosted)
+type EvictionKey = (String, u64, u128, u64, io::Result<u64>, u128, bool);
+
+type ItemMap<S> = HashMap<String, DatabaseItem, S>;
+
+/// Hasher of the keys, unless another one is given to `FastDB::with_hasher`.
+/// It is fast, but not DoS resistant.
+pub type DefaultHashBuilder = BuildHasherDefault<XxHash64>;
+
+/// The items and their access order, changed together under the same lock.
+/// Reads go straight to the map, changes go through the methods below.
+/// `unused` is the last `unused_bytes` added to the `FastDB` wide counter shared by all shards,
+/// updated whenever an item is added or removed.
+#[derive(Debug)]
+struct Items<S> {
+    map: ItemMap<S>,
+    lru: LruIndex<S>,
+    unused: u64,
+    index_overhead: Arc<AtomicU64>,
+}
+
+impl<S> Deref for Items<S> {
+    type Target = ItemMap<S>;
+
+    fn deref(&self) -> &ItemMap<S> {
+        &self.map
+    }
+}
+
+impl<S: BuildHasher + Clone> Items<S> {
+    fn with_capacity_and_hasher(
+        capacity: usize,
+        hasher: S,
+        index_overhead: Arc<AtomicU64>,
+    ) -> Self {
+        let mut items = Self {
+            map: HashMap::with_capacity_and_hasher(capacity, hasher.clone()),
+            lru: LruIndex::with_capacity_and_hasher(capacity, hasher),
+            unused: 0,
+            index_overhead,
+        };
+        items.track_unused();
+        items
+    }
+
+    fn insert(&mut self, key: String, value: DatabaseItem) -> Option<DatabaseItem> {
+        self.lru.touch(&key, value.last_access);
+        let previous = self.map.insert(key, value);
+        self.track_unused();
+        previous
+    }
+
+    fn remove(&mut self, key: &str) -> Option<DatabaseItem> {
+        self.lru.remove(key);
+        let removed = self.map.remove(key);
+        self.track_unused();
+        removed
+    }
+
+    fn drain(&mut self) -> Vec<(String, DatabaseItem)> {
+        self.lru.clear();
+        let drained = self.map.drain().collect();
+        self.track_unused();
+        drained
+    }
+
+    /// Moves the shared counter by the change of `unused_bytes` since the last call.
+    /// Only the number of items and the capacities change it, so this is O(1).
+    fn track_unused(&mut self) {
+        let unused = self.unused_bytes();
+        if unused != self.unused {
+            // Added before subtracting, so the counter can't wrap below 0 meanwhile.
+            self.index_overhead.fetch_add(unused, Ordering::Relaxed);
+            self.index_overhead.fetch_sub(self.unused, Ordering::Relaxed);
+            self.unused = unused;
+        }
+    }
+
+    /// Changes to `last_access` must go through `touch` instead, to keep the access order.
+    fn get_mut(&mut self, key: &str) -> Option<&mut DatabaseItem> {
+        self.map.get_mut(key)
+    }
+
+    /// Applies `f` to the item of `key`, keeping the access order if it changed `last_access`.
+    fn update<F: FnOnce(&mut DatabaseItem)>(&mut self, key: &str, f: F) -> bool {
+        match self.map.get_mut(key) {
+            Some(v) => {
+                f(v);
+                self.lru.touch(key, v.last_access);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn touch(&mut self, key: &str, now: u128) -> Option<&mut DatabaseItem> {
+        let v = self.map.get_mut(key)?;
+        v.last_access = now;
+        self.lru.touch(key, now);
+        Some(v)
+    }
+
+    /// Bytes allocated beyond `entry_overhead` of the items, see `FastDB::index_overhead_bytes`.
+    fn unused_bytes(&self) -> u64 {
+        let bucket_size = std::mem::size_of::<(String, DatabaseItem)>() as u64 + 1;
+        let unused_buckets = (self.map.capacity() - self.map.len()) as u64;
+        unused_buckets * bucket_size + self.lru.unused_bytes()
+    }
+
+    /// Items from least to most recently accessed.
+    fn by_last_access(&self) -> impl Iterator<Item = (&str, &DatabaseItem)> + '_ {
+        self.lru.iter().map(move |k| (k, &self.map[k]))
+    }
+}
+
+type ReadGuard<'a, S> = lock_api::RwLockReadGuard<'a, parking_lot::RawRwLock, Items<S>>;
+type WriteGuard<'a, S> = lock_api::RwLockWriteGuard<'a, parking_lot::RawRwLock, Items<S>>;
+
+/// Guards of all shards, taken in shard order.
+/// Routes single keys to their shard and iterates over all of them.
+struct Shards<G>(Vec<G>);
+
+impl<S: BuildHasher + Clone + 'static, G: Deref<Target = Items<S>>> Shards<G> {
+    fn shard(&self, key: &str) -> &Items<S> {
+        &self.0[shard_index(key, self.0.len())]
+    }
+
+    fn get(&self, key: &str) -> Option<&DatabaseItem> {
+        self.shard(key).get(key)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&String, &DatabaseItem)> + '_ {
+        self.0.iter().flat_map(|v| v.iter())
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|v| v.len()).sum()
+    }
+
+    /// Items of all shards from least to most recently accessed.
+    fn by_last_access(&self) -> impl Iterator<Item = (&str, &DatabaseItem)> + '_ {
+        let mut heads: Vec<_> = self.0.iter().map(|v| v.by_last_access().peekable()).collect();
+        std::iter::from_fn(move || {
+            let (_, next) = heads
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, v)| v.peek().map(|(_, item)| (item.last_access, i)))
+                .min()?;
+            heads[next].next()
+        })
+    }
+}
+
+impl<S: BuildHasher + Clone + 'static, G: DerefMut<Target = Items<S>>> Shards<G> {
+    fn shard_mut(&mut self, key: &str) -> &mut Items<S> {
+        let idx = shard_index(key, self.0.len());
+        &mut self.0[idx]
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut DatabaseItem> {
+        self.shard_mut(key).get_mut(key)
+    }
+
+    fn insert(&mut self, key: String, value: DatabaseItem) -> Option<DatabaseItem> {
+        self.shard_mut(&key).insert(key, value)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<DatabaseItem> {
+        self.shard_mut(key).remove(key)
+    }
+
+    fn drain(&mut self) -> Vec<(String, DatabaseItem)> {
+        self.0.iter_mut().flat_map(|v| v.drain()).collect()
+    }
+}
+
+/// The shard holding `key`.
+/// Always picked by XxHash64, the hasher of `FastDB` only applies within the shards.
+fn shard_index(key: &str, shards: usize) -> usize {
+    (BuildHasherDefault::<XxHash64>::default().hash_one(key) % shards as u64) as usize
+}
+
+/// The items, split into shards by the hash of their key,
+/// so writes to keys of different shards don't wait for each other.
+/// Operations on single keys lock their shard, operations on all items lock every shard.
+/// Keys are hashed with `S`, see `DefaultHashBuilder`.
 #[derive(Debug, Clone)]
-pub struct FastDB {
-    hashmap: Arc<RwLock<HashMap<String, DatabaseItem, BuildHasherDefault<XxHash64>>>>,
+pub struct FastDB<S = DefaultHashBuilder> {
+    shards: Arc<Vec<RwLock<Items<S>>>>,
+    lock_contention: Arc<LockContention>,
+    index_overhead: Arc<AtomicU64>,
 }
 
 impl Default for FastDB {
     fn default() -> Self {
-        Self {
-            hashmap: Arc::new(RwLock::new(HashMap::<
-                String,
-                DatabaseItem,
-                BuildHasherDefault<XxHash64>,
-            >::default())),
-        }
+        Self::with_shards(num_cpus::get_physical())
     }
 }
 
 impl FastDB {
+    /// Creates a database split into `shards` shards, at least one.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_hasher(shards, DefaultHashBuilder::default())
+    }
+
+    /// Creates a database with room for `capacity` items before it has to grow,
+    /// e.g. for loading a known number of items.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(
+            num_cpus::get_physical(),
+            capacity,
+            DefaultHashBuilder::default(),
+        )
+    }
+}
+
+impl<S: BuildHasher + Clone + 'static> FastDB<S> {
+    /// Creates a database split into `shards` shards, at least one, hashing keys with `hasher`.
+    /// E.g. `std::collections::hash_map::RandomState` for keys chosen by untrusted clients.
+    pub fn with_hasher(shards: usize, hasher: S) -> Self {
+        Self::with_capacity_and_hasher(shards, 0, hasher)
+    }
+
+    /// Like `with_hasher`, with room for `capacity` items spread evenly over the shards.
+    pub fn with_capacity_and_hasher(shards: usize, capacity: usize, hasher: S) -> Self {
+        let shards = shards.max(1);
+        let per_shard = capacity.div_ceil(shards);
+        let index_overhead = Arc::new(AtomicU64::new(0));
+        Self {
+            shards: Arc::new(
+                (0..shards)
+                    .map(|_| {
+                        let items = Items::with_capacity_and_hasher(
+                            per_shard,
+                            hasher.clone(),
+                            Arc::clone(&index_overhead),
+                        );
+                        RwLock::new(items)
+                    })
+                    .collect(),
+            ),
+            lock_contention: Arc::new(LockContention::default()),
+            index_overhead,
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Number of items the shards can hold before they have to grow.
+    pub fn capacity(&self) -> usize {
+        (0..self.shards.len())
+            .map(|i| self.lock_shard_read(i).capacity())
+            .sum()
+    }
+
+    /// Measure the time spent waiting for the lock.
+    pub fn set_lock_contention_stats(&self, enabled: bool) {
+        self.lock_contention.set_enabled(enabled);
+    }
+
+    pub fn lock_contention(&self) -> LockContentionSnapshot {
+        self.lock_contention.snapshot()
+    }
+
+    fn lock_shard_read(&self, idx: usize) -> ReadGuard<'_, S> {
+        let shard = &self.shards[idx];
+        if !self.lock_contention.is_enabled() {
+            return shard.read();
+        }
+        if let Some(v) = shard.try_read() {
+            return v;
+        }
+
+        let started = Instant::now();
+        let guard = shard.read();
+        self.lock_contention.record_read_wait(started.elapsed());
+        guard
+    }
+
+    fn lock_shard_write(&self, idx: usize) -> WriteGuard<'_, S> {
+        let shard = &self.shards[idx];
+        if !self.lock_contention.is_enabled() {
+            return shard.write();
+        }
+        if let Some(v) = shard.try_write() {
+            return v;
+        }
+
+        let started = Instant::now();
+        let guard = shard.write();
+        self.lock_contention.record_write_wait(started.elapsed());
+        guard
+    }
+
+    /// Read locks the shard of `key`.
+    fn read_lock(&self, key: &str) -> ReadGuard<'_, S> {
+        self.lock_shard_read(shard_index(key, self.shards.len()))
+    }
+
+    /// Write locks the shard of `key`.
+    fn write_lock(&self, key: &str) -> WriteGuard<'_, S> {
+        self.lock_shard_write(shard_index(key, self.shards.len()))
+    }
+
+    /// Read locks all shards, in order.
+    fn read_all(&self) -> Shards<ReadGuard<'_, S>> {
+        Shards((0..self.shards.len()).map(|i| self.lock_shard_read(i)).collect())
+    }
+
+    /// Write locks all shards, in order.
+    fn write_all(&self) -> Shards<WriteGuard<'_, S>> {
+        Shards((0..self.shards.len()).map(|i| self.lock_shard_write(i)).collect())
+    }
+
     pub fn set(&mut self, key: String, value: DatabaseItem) -> io::Result<Option<DatabaseItem>> {
-        let hashmap = Arc::<
-            lock_api::RwLock<
-                parking_lot::RawRwLock,
-                HashMap<std::string::String, DatabaseItem, BuildHasherDefault<XxHash64>>,
-            >,
-        >::clone(&self.hashmap);
-        let mut hashmap = hashmap.write();
+        let mut hashmap = self.write_lock(&key);
         Ok(hashmap.insert(key, value))
     }
 
-    pub fn get(&mut self, key: &str) -> io::Result<Option<DatabaseItem>> {
-        let hashmap = &self.hashmap.read();
+    /// Inserts all `items` while all shards are write locked, returning the replaced items.
+    pub fn set_many(&mut self, items: Vec<(String, DatabaseItem)>) -> Vec<Option<DatabaseItem>> {
+        let mut hashmap = self.write_all();
+        items
+            .into_iter()
+            .map(|(k, v)| hashmap.insert(k, v))
+            .collect()
+    }
+
+    /// Clones the items of `keys` while all shards are read locked.
+    pub fn get_many(&self, keys: &[String]) -> Vec<Option<DatabaseItem>> {
+        let hashmap = self.read_all();
+        keys.iter().map(|k| hashmap.get(k).cloned()).collect()
+    }
+
+    /// Like `get_and_touch` for all `keys`, while all shards are write locked.
+    /// Items expired at `now` are removed instead and returned separately,
+    /// so a key can't expire between the check and the touch.
+    pub fn touch_many(
+        &self,
+        keys: &[String],
+        now: u128,
+        default_ttl: u128,
+    ) -> (Vec<Option<DatabaseItem>>, Vec<(String, DatabaseItem)>) {
+        let mut hashmap = self.write_all();
+        let mut expired = vec![];
+        let items = keys
+            .iter()
+            .map(|k| {
+                let shard = hashmap.shard_mut(k);
+                if shard.get(k)?.is_expired(now, default_ttl) {
+                    if let Some(v) = shard.remove(k) {
+                        expired.push((k.to_owned(), v));
+                    }
+                    return None;
+                }
+                shard.touch(k, now).map(|v| {
+                    v.access_counter += 1;
+                    v.clone()
+                })
+            })
+            .collect();
+        (items, expired)
+    }
+
+    pub fn get(&self, key: &str) -> io::Result<Option<DatabaseItem>> {
+        let hashmap = &self.read_lock(key);
         let f = hashmap.get(key).cloned();
         Ok(f)
     }
 
+    /// Updates the access stats of `key` and returns the updated item, under a single write lock.
+    pub fn get_and_touch(&self, key: &str, now: u128) -> Option<DatabaseItem> {
+        let hashmap = &mut self.write_lock(key);
+        hashmap.touch(key, now).map(|v| {
+            v.access_counter += 1;
+            v.clone()
+        })
+    }
+
+    /// Applies `f` to the item of `key` in place, under a single write lock.
+    /// Returns whether the key exists.
+    pub fn update<F: FnOnce(&mut DatabaseItem)>(&self, key: &str, f: F) -> bool {
+        self.write_lock(key).update(key, f)
+    }
+
+    /// Sets the time (in nanoseconds) until which `key` is evicted last.
+    /// Returns whether the key exists.
+    pub fn boost(&self, key: &str, until: u128) -> bool {
+        let hashmap = &mut self.write_lock(key);
+        match hashmap.get_mut(key) {
+            Some(v) => {
+                v.boosted_until = Some(until);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns up to `n` keys in the order `cleanup_strategy` would evict them.
+    pub fn eviction_candidates(&self, cleanup_strategy: &CleanseStrategy, n: usize) -> Vec<String> {
+        let hashmap = &self.read_all();
+        Self::get_keys(hashmap, cleanup_strategy)
+            .take(n)
+            .map(|k| k.0)
+            .collect()
+    }
+
+    /// Estimates the memory allocated by the map and the LRU index beyond what
+    /// `entry_overhead` counts for their entries: unused buckets with their control bytes,
+    /// unused nodes and the free list of the index.
+    /// Kept up to date on every insert and remove, so reading it takes no lock.
+    pub fn index_overhead_bytes(&self) -> u64 {
+        self.index_overhead.load(Ordering::Relaxed)
+    }
+
+    /// Clones every item that has a disk file.
+    pub fn disk_items(&self) -> Vec<(String, DatabaseItem)> {
+        let hashmap = &self.read_all();
+        hashmap
+            .iter()
+            .filter(|(_, v)| v.filepath.is_some())
+            .map(|(k, v)| (k.to_owned(), v.clone()))
+            .collect()
+    }
+
+    /// Number of items, in memory and on disk.
+    pub fn len(&self) -> usize {
+        self.read_all().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns all keys, taken while all shards are read locked.
+    pub fn keys(&self) -> Vec<String> {
+        let hashmap = &self.read_all();
+        hashmap.iter().map(|(k, _)| k.to_owned()).collect()
+    }
+
+    /// Returns key, last access, access counter, whether the item is on disk and creation time
+    /// for all items, taken while all shards are read locked, without cloning the values.
+    pub fn metadata(&self) -> Vec<(String, u128, u64, bool, u128)> {
+        let hashmap = &self.read_all();
+        hashmap
+            .iter()
+            .map(|(k, v)| {
+                let on_disk = v.is_on_disk();
+                (k.to_owned(), v.last_access, v.access_counter, on_disk, v.created_at)
+            })
+            .collect()
+    }
+
+    /// Returns the number of items and of items whose value is only on disk,
+    /// counted while all shards are read locked.
+    pub fn item_counts(&self) -> (usize, usize) {
+        let hashmap = &self.read_all();
+        let on_disk = hashmap.iter().filter(|(_, v)| v.value.is_none()).count();
+        (hashmap.len(), on_disk)
+    }
+
+    /// Clones all items while all shards are read locked.
+    pub fn snapshot(&self) -> Vec<(String, DatabaseItem)> {
+        let hashmap = &self.read_all();
+        hashmap
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.clone()))
+            .collect()
+    }
+
+    /// Whether `key` is stored, without cloning or touching the item.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.read_lock(key).contains_key(key)
+    }
+
+    /// Returns the stored value hash of `key` without cloning the item.
+    pub fn get_value_hash(&self, key: &str) -> Option<u64> {
+        let hashmap = &self.read_lock(key);
+        hashmap.get(key).and_then(|v| v.value_hash)
+    }
+
     pub fn del(&mut self, key: &str) -> io::Result<Option<DatabaseItem>> {
-        let hashmap = &mut self.hashmap.write();
+        let hashmap = &mut self.write_lock(key);
 
         Ok(hashmap.remove(key))
     }
 
+    /// Removes `key` if `f` holds for its item, checked under the same write lock,
+    /// so an item replaced after it was looked at is kept.
+    pub fn remove_if<F: FnOnce(&DatabaseItem) -> bool>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Option<DatabaseItem> {
+        let hashmap = &mut self.write_lock(key);
+        if f(hashmap.get(key)?) {
+            hashmap.remove(key)
+        } else {
+            None
+        }
+    }
+
+    /// Removes and returns all items while all shards are write locked.
+    pub fn drain(&mut self) -> Vec<(String, DatabaseItem)> {
+        self.write_all().drain()
+    }
+
+    /// Removes and returns all items whose key starts with `prefix`, without touching their files.
+    pub fn drain_prefix(&mut self, prefix: &str) -> Vec<(String, DatabaseItem)> {
+        let mut hashmap = self.write_all();
+        let keys: Vec<String> = hashmap
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, _)| k.to_owned())
+            .collect();
+
+        keys.into_iter()
+            .filter_map(|k| hashmap.remove(&k).map(|v| (k, v)))
+            .collect()
+    }
+
+    /// Removes disk files until `to_clean` bytes are freed.
+    /// Items only on disk are dropped, items also held in memory only lose their file.
+    /// Items whose folder can't be removed are kept as they are, the error is logged.
+    /// Returns the freed disk bytes and the memory freed by dropped items.
     pub fn cleanup_disk(
         &mut self,
         cleanup_strategy: &CleanseStrategy,
         mut to_clean: u64,
-        cache_path: &str,
-    ) -> io::Result<()> {
-        let hashmap = Arc::<
-            lock_api::RwLock<
-                parking_lot::RawRwLock,
-                HashMap<std::string::String, DatabaseItem, BuildHasherDefault<XxHash64>>,
-            >,
-        >::clone(&self.hashmap);
-
-        let mut hashmap = hashmap.write();
-
-        let keys = self.get_keys(&hashmap, cleanup_strategy);
+    ) -> io::Result<(u64, u64)> {
+        let mut hashmap = self.write_all();
 
-        logger::warn(&format!("{} {} {:?}", to_clean, cache_path, keys));
+        let keys = Self::get_keys(&hashmap, cleanup_strategy);
 
-        let mut to_remove: Vec<String> = vec![];
+        let mut to_remove: Vec<(String, u64)> = vec![];
 
         for k in keys {
             if to_clean == 0 {
@@ -155,14 +889,10 @@ impl FastDB {
             }
 
             match &k.4 {
+                Ok(0) => continue,
                 Ok(v) => {
-                    to_remove.push(k.0.clone());
-
-                    if to_clean >= *v {
-                        to_clean -= *v;
-                    } else {
-                        to_clean = 0;
-                    }
+                    to_remove.push((k.0.clone(), *v));
+                    to_clean = to_clean.saturating_sub(*v);
                 }
                 Err(v) => {
                     logger::error(&format!("\t\tSkipping {:?} ERROR: {:?}", k.0, v));
@@ -170,75 +900,170 @@ impl FastDB {
             }
         }
 
-        for k in &to_remove {
-            let folder_path = format!("{}/{}", cache_path, k);
-
-            if Path::new(&folder_path).exists() {
-                remove_dir_all(&folder_path)?;
+        let mut freed: u64 = 0;
+        let mut mem_freed: u64 = 0;
+        for (k, disk_size) in &to_remove {
+            let folder = hashmap.get(k).and_then(|v| v.filepath.as_ref()).and_then(|f| f.parent());
+            if let Some(folder) = folder.filter(|v| v.exists()) {
+                // The item stays as it is, so only what was actually removed is accounted.
+                if let Err(e) = remove_dir_all(folder) {
+                    logger::error(&format!("\t\tSkipping {:?} ERROR: {:?}", k, e));
+                    continue;
+                }
             }
+            freed += disk_size;
 
-            hashmap.remove(k);
+            let in_memory = hashmap.get(k).is_some_and(|v| v.value.is_some());
+            if in_memory {
+                if let Some(f) = hashmap.get_mut(k) {
+                    f.filepath = None;
+                    f.chunks = 0;
+                    f.compressed = false;
+                }
+            } else if let Some(f) = hashmap.remove(k) {
+                mem_freed += f.get_mem_size() + entry_overhead(k);
+            }
         }
 
         logger::debug(&format!("\tKeys to remove ({:?}): {:?}", &to_remove.len() , &to_remove));
 
-        Ok(())
+        Ok((freed, mem_freed))
     }
 
-    fn get_keys(
-        &mut self,
-        hashmap: &lock_api::RwLockWriteGuard<
-            '_,
-            parking_lot::RawRwLock,
-            std::collections::HashMap<
-                std::string::String,
-                DatabaseItem,
-                std::hash::BuildHasherDefault<twox_hash::XxHash64>,
-            >,
-        >,
+    /// Items in the order `cleanup_strategy` evicts them, boosted items last.
+    /// `LastAccess` follows the access order index, so only the consumed items are looked at.
+    /// The other strategies sort all items.
+    fn get_keys<'a, G: Deref<Target = Items<S>> + 'a>(
+        hashmap: &'a Shards<G>,
         cleanup_strategy: &CleanseStrategy,
-    ) -> Vec<(String, u64, u128, u64, io::Result<u64>)> {
-        let mut keys: Vec<(String, u64, u128, u64, io::Result<u64>)> = vec![];
+    ) -> Box<dyn Iterator<Item = EvictionKey> + 'a> {
+        let now = get_nano_time();
 
-        for (k, v) in hashmap.iter() {
-            keys.push((
-                k.to_owned(),
-                v.access_counter,
-                v.last_access,
-                v.get_mem_size(),
-                v.get_disk_size(),
-            ))
+        if let CleanseStrategy::LastAccess = cleanup_strategy {
+            let ordered = move |boosted: bool| {
+                hashmap
+                    .by_last_access()
+                    .map(move |(k, v)| eviction_key(k, v, now))
+                    .filter(move |k| k.6 == boosted)
+            };
+            return Box::new(ordered(false).chain(ordered(true)));
         }
 
+        let mut keys: Vec<EvictionKey> = hashmap
+            .iter()
+            .map(|(k, v)| eviction_key(k, v, now))
+            .collect();
+
         match cleanup_strategy {
-            CleanseStrategy::LastAccess => {
-                keys.sort_by(|a, b| a.2.cmp(&b.2));
-            }
+            CleanseStrategy::LastAccess => {}
             CleanseStrategy::LeastUsed => {
-                keys.sort_by(|a, b| a.1.cmp(&b.1));
+                // Ties are broken by age and finally by key, so the order doesn't depend on the map.
+                keys.sort_by(|a, b| {
+                    a.1.cmp(&b.1)
+                        .then(a.2.cmp(&b.2))
+                        .then(a.5.cmp(&b.5))
+                        .then(a.0.cmp(&b.0))
+                });
             }
             CleanseStrategy::Combined => {
-                keys.sort_by(|a, b| a.1.cmp(&b.1).then(a.2.cmp(&b.2)));
+                keys.sort_by(|a, b| a.1.cmp(&b.1).then(a.2.cmp(&b.2)).then(a.0.cmp(&b.0)));
             }
+            CleanseStrategy::Fifo => {
+                keys.sort_by(|a, b| a.5.cmp(&b.5).then(a.0.cmp(&b.0)));
+            }
+            CleanseStrategy::SizeWeighted => {
+                // By the size in memory and on disk, so it also applies to disk items.
+                let size = |k: &EvictionKey| k.3 + k.4.as_ref().map_or(0, |v| *v);
+                keys.sort_by(|a, b| size(b).cmp(&size(a)).then(a.0.cmp(&b.0)));
+            }
+        }
+        // Boosted items are evicted last, in the order of the strategy.
+        keys.sort_by_key(|k| k.6);
+        Box::new(keys.into_iter())
+    }
+
+    /// Loads disk items back into memory, most valuable first according to `cleanup_strategy`,
+    /// until `to_clean` disk bytes are freed or the next loaded value doesn't fit into `headroom` bytes.
+    /// Items that can't be loaded are skipped and left to be dropped from disk.
+    /// Returns the bytes added in memory and the bytes freed on disk.
+    pub fn promote_disk_items(
+        &mut self,
+        cleanup_strategy: &CleanseStrategy,
+        to_clean: u64,
+        mut headroom: u64,
+    ) -> io::Result<(u64, u64)> {
+        let hashmap = &mut self.write_all();
+
+        let keys: Vec<EvictionKey> = Self::get_keys(hashmap, cleanup_strategy).collect();
+
+        let mut added: u64 = 0;
+        let mut freed: u64 = 0;
+
+        for k in keys.into_iter().rev() {
+            if freed >= to_clean {
+                break;
+            }
+
+            let disk_size = match &k.4 {
+                Ok(v) if *v > 0 => *v,
+                _ => continue,
+            };
+
+            let mut f = hashmap.get(&k.0).cloned().expect("Key went missing");
+            if f.value.is_some() {
+                continue;
+            }
+
+            let mem_size = f.get_mem_size();
+            f.value = match f.load_value() {
+                Ok(Some(v)) => Some(v),
+                Ok(None) => continue,
+                Err(e) => {
+                    logger::error(&format!("\t\tSkipping {:?} ERROR: {:?}", k.0, e));
+                    continue;
+                }
+            };
+            // Compressed values take more memory than disk, so the loaded value has to fit.
+            let mem_added = f.get_mem_size() - mem_size;
+            if mem_added > headroom {
+                break;
+            }
+
+            if let Some(folder) = f.filepath.take().as_ref().and_then(|v| v.parent()) {
+                if let Err(e) = remove_dir_all(folder) {
+                    logger::error(&format!("\t\tSkipping {:?} ERROR: {:?}", k.0, e));
+                    continue;
+                }
+            }
+            f.chunks = 0;
+            f.compressed = false;
+
+            logger::debug(&format!("\t\tPromoting {:?} to memory", &k.0));
+
+            headroom = headroom.saturating_sub(mem_added);
+            added += mem_added;
+            freed += disk_size;
+
+            hashmap.insert(k.0, f);
         }
-        keys
+
+        Ok((added, freed))
     }
 
+    /// Moves items from memory to disk until `to_clean` bytes are freed.
+    /// The values are written on `pool` in parallel, or one after another without one.
+    /// Returns the bytes freed in memory, the bytes written to disk and the number of skipped items.
     pub fn cleanup_mem(
         &mut self,
         cleanup_strategy: &CleanseStrategy,
         mut to_clean: u64,
         cache_path: &str,
-    ) -> io::Result<u64> {
-        let hashmap = Arc::<
-            lock_api::RwLock<
-                parking_lot::RawRwLock,
-                HashMap<std::string::String, DatabaseItem, BuildHasherDefault<XxHash64>>,
-            >,
-        >::clone(&self.hashmap);
-        let mut hashmap = hashmap.write();
+        format: DiskFormat,
+        pool: Option<&ThreadPool>,
+    ) -> io::Result<(u64, u64, u64)> {
+        let mut hashmap = self.write_all();
 
-        let keys = self.get_keys(&hashmap, cleanup_strategy);
+        let keys = Self::get_keys(&hashmap, cleanup_strategy);
 
         let mut to_disk: Vec<String> = vec![];
 
@@ -246,20 +1071,25 @@ impl FastDB {
             if to_clean == 0 {
                 break;
             }
-            logger::log("");
-            logger::log(&format!("\t\tLeft to clean:{}", fmt_bytes(to_clean)));
-            logger::log(&format!(
-                "\t\t{:?}: {:?} {:?} {:?} {:?}",
-                &k.0,
-                &k.1,
-                tools::nano_time_fmt(k.2),
-                &k.3,
-                &k.4
-            ));
+            if logger::enabled(logger::Severity::TRACE) {
+                logger::keyed(
+                    logger::Severity::TRACE,
+                    &k.0,
+                    &format!(
+                        "left to clean: {}, access_counter: {:?}, last_access: {}, mem_size: {:?}, disk_size: {:?}",
+                        fmt_bytes(to_clean),
+                        &k.1,
+                        tools::nano_time_fmt(k.2),
+                        &k.3,
+                        &k.4
+                    ),
+                );
+            }
 
             match &k.4 {
-                Ok(v) => {
-                    if v == &0 {
+                Ok(_) => {
+                    let in_memory = hashmap.get(&k.0).is_some_and(|v| v.value.is_some());
+                    if in_memory {
                         to_disk.push(k.0.clone());
                         logger::debug(&format!(
                             "\t\tMoving {:?} to disk will yield: {}",
@@ -281,35 +1111,271 @@ impl FastDB {
 
         logger::debug(&format!("\tKeys to disk ({:?}): {:?}", &to_disk.len() , &to_disk));
 
-        let mut ds: u64 = 0;
+        Self::spill(&mut hashmap, to_disk, cache_path, format, pool)
+    }
 
-        for k in to_disk {
-            let mut f = hashmap.get(&k).cloned().expect("Key went missing");
+    /// Removes the first `n` items in `cleanup_strategy` order, including their disk folders.
+    /// Items whose disk folder can't be removed are still dropped, the error is logged.
+    /// Returns the bytes freed in memory and on disk.
+    pub fn remove_entries(&mut self, cleanup_strategy: &CleanseStrategy, n: usize) -> (u64, u64) {
+        let mut hashmap = self.write_all();
+        let keys: Vec<EvictionKey> = Self::get_keys(&hashmap, cleanup_strategy).take(n).collect();
 
-            let folder_path = format!("{}/{}", cache_path, k);
-            f.filepath = Some(PathBuf::from(&folder_path));
+        let mut mem_freed: u64 = 0;
+        let mut disk_freed: u64 = 0;
+        for k in keys {
+            let f = hashmap.remove(&k.0).expect("Key went missing");
+            mem_freed += f.get_mem_size() + entry_overhead(&k.0);
+            let (disk_size, removed) = remove_disk_files(&f);
+            disk_freed += disk_size;
+            if let Err(e) = removed {
+                logger::error(&format!("\t\tCouldn't remove files of {:?}: {:?}", &k.0, e));
+            }
+            logger::debug(&format!("\t\tRemoving {:?}", &k.0));
+        }
+
+        (mem_freed, disk_freed)
+    }
 
-            if Path::new(&folder_path).exists() {
-                remove_dir_all(&folder_path)?;
+    /// Removes every expired item at `now`, see `DatabaseItem::is_expired`, including its disk folder.
+    /// Items whose disk folder can't be removed are still dropped, the error is logged.
+    /// Returns the bytes freed in memory and on disk.
+    pub fn remove_expired(&mut self, now: u128, default_ttl: u128) -> (u64, u64) {
+        let mut hashmap = self.write_all();
+
+        // Items with their own ttl can expire anywhere in the access order, so all are checked.
+        let expired: Vec<String> = hashmap
+            .iter()
+            .filter(|(_, v)| v.is_expired(now, default_ttl))
+            .map(|(k, _)| k.to_owned())
+            .collect();
+
+        let mut mem_freed: u64 = 0;
+        let mut disk_freed: u64 = 0;
+        for k in expired {
+            let f = hashmap.remove(&k).expect("Key went missing");
+            mem_freed += f.get_mem_size() + entry_overhead(&k);
+            let (disk_size, removed) = remove_disk_files(&f);
+            disk_freed += disk_size;
+            if let Err(e) = removed {
+                logger::error(&format!("\t\tCouldn't remove files of {:?}: {:?}", &k, e));
             }
+            logger::debug(&format!("\t\tExpired {:?}", &k));
+        }
+
+        (mem_freed, disk_freed)
+    }
 
-            create_dir_all(&folder_path)?;
+    /// Moves the in memory values of items older than `cutoff` (by `last_access`) to disk,
+    /// written like in `cleanup_mem`.
+    /// Returns the bytes freed in memory, the bytes written to disk and the number of skipped items.
+    pub fn spill_older_than(
+        &mut self,
+        cutoff: u128,
+        cache_path: &str,
+        format: DiskFormat,
+        pool: Option<&ThreadPool>,
+    ) -> io::Result<(u64, u64, u64)> {
+        let hashmap = &mut self.write_all();
+
+        let to_disk: Vec<String> = hashmap
+            .by_last_access()
+            .take_while(|(_, v)| v.last_access < cutoff)
+            .filter(|(_, v)| v.value.is_some())
+            .map(|(k, _)| k.to_owned())
+            .collect();
+
+        logger::debug(&format!("\tKeys to disk ({:?}): {:?}", &to_disk.len(), &to_disk));
+
+        Self::spill(hashmap, to_disk, cache_path, format, pool)
+    }
 
-            let file_path = format!("{}/cachefile", &folder_path);
+    /// Writes the values of `to_disk` and drops them from memory.
+    /// Every key has its own folder, so the writes are independent and run on `pool` if given.
+    /// The items are updated afterwards, including those written before a failed write.
+    /// Keys that went missing or items without a value are skipped with a warning.
+    fn spill(
+        hashmap: &mut Shards<WriteGuard<'_, S>>,
+        to_disk: Vec<String>,
+        cache_path: &str,
+        format: DiskFormat,
+        pool: Option<&ThreadPool>,
+    ) -> io::Result<(u64, u64, u64)> {
+        let mut skipped: u64 = 0;
+        let items: Vec<(String, DatabaseItem)> = to_disk
+            .into_iter()
+            .filter_map(|k| match hashmap.get(&k) {
+                Some(f) => Some((k, f.clone())),
+                None => {
+                    logger::warn(&format!("\t\tSkipping {:?}: key went missing", k));
+                    skipped += 1;
+                    None
+                }
+            })
+            .collect();
 
-            let mut file = get_non_buffered_file_handle(&file_path)?;
+        type Written = Option<(String, DatabaseItem, u64, u64)>;
+        let write = |(k, mut f): (String, DatabaseItem)| -> io::Result<Written> {
+            let mem_size = f.get_mem_size();
+            let mut ds: u64 = 0;
 
-            let value = &f.value.expect("f has no value !");
-            file.write_all(value)?;
+            if !f.is_on_disk() {
+                let value = match f.value.as_ref() {
+                    Some(v) => Arc::clone(v),
+                    None => {
+                        logger::warn(&format!(
+                            "\t\tSkipping {:?}: neither in memory nor on disk",
+                            k
+                        ));
+                        return Ok(None);
+                    }
+                };
+                f.write_to_disk(cache_path, &k, &value, format)?;
+                ds = f.get_disk_size()?;
+            }
 
-            f.filepath = Some(PathBuf::from(&file_path));
             f.value = None;
+            let freed = mem_size.saturating_sub(f.get_mem_size());
+            Ok(Some((k, f, freed, ds)))
+        };
+        let written: Vec<io::Result<Written>> = match pool {
+            Some(pool) => pool.install(|| items.into_par_iter().map(write).collect()),
+            None => items.into_iter().map(write).collect(),
+        };
+
+        let mut ds: u64 = 0;
+        let mut freed: u64 = 0;
+        let mut error = None;
+        for v in written {
+            match v {
+                Ok(Some((k, f, item_freed, item_ds))) => {
+                    freed += item_freed;
+                    ds += item_ds;
+                    hashmap.insert(k, f);
+                }
+                Ok(None) => skipped += 1,
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok((freed, ds, skipped)),
+        }
+    }
+}
 
-            ds += f.get_disk_size()?;
+/// Removes the disk folder of an item taken out of the database.
+/// Returns the disk size it had, counted before removing anything,
+/// so callers can account for it even if removing the folder fails.
+/// Files whose size can't be read count as 0.
+pub fn remove_disk_files(item: &DatabaseItem) -> (u64, io::Result<()>) {
+    let disk_size = item.get_disk_size().unwrap_or_else(|e| {
+        logger::warn(&format!("\t\tCouldn't read size of {:?}: {:?}", item.filepath, e));
+        0
+    });
+    let removed = match item.filepath.as_ref().and_then(|v| v.parent()) {
+        Some(folder) if folder.exists() => remove_dir_all(folder),
+        _ => Ok(()),
+    };
+    (disk_size, removed)
+}
+
+fn eviction_key(k: &str, v: &DatabaseItem, now: u128) -> EvictionKey {
+    (
+        k.to_owned(),
+        v.access_counter,
+        v.last_access,
+        v.get_mem_size(),
+        v.get_disk_size(),
+        v.created_at,
+        v.boosted_until.is_some_and(|until| until > now),
+    )
+}
+
+/// Writes `value` to `cachefile` in the folder of `key` given by `format.layout`,
+/// and returns the path of the written file and the number of chunks.
+/// Compressed values start with a byte naming the codec, so files written with different
+/// codecs can be read back.
+/// Values bigger than `max_chunk_size` are split into `cachefile.0`, `cachefile.1`, ...,
+/// in which case the returned path is the one of the (absent) unsplit cachefile.
+/// Every file is written to a `.tmp` file first and renamed into place once synced.
+/// An existing folder is reused, so a new key costs one directory creation and an existing key none.
+pub fn write_cachefile(
+    cache_path: &str,
+    key: &str,
+    value: &[u8],
+    format: DiskFormat,
+) -> io::Result<(PathBuf, u32)> {
+    let compressed = compress(value, format.compression)?;
+    let value = compressed.as_deref().unwrap_or(value);
+    let folder_path = format.layout.folder(cache_path, key);
+
+    if !Path::new(&folder_path).is_dir() {
+        create_dir_all(&folder_path)?;
+    }
+
+    let file_path = PathBuf::from(format!("{}/cachefile", &folder_path));
 
-            hashmap.insert(k, f);
+    let chunks = match format.max_chunk_size {
+        Some(v) if v > 0 && value.len() as u64 > v => {
+            let mut chunks: u32 = 0;
+            for chunk in value.chunks(v as usize) {
+                write_atomic(chunk_path(&file_path, chunks), chunk)?;
+                chunks += 1;
+            }
+            if file_path.exists() {
+                fs::remove_file(&file_path)?;
+            }
+            chunks
+        }
+        _ => {
+            write_atomic(&file_path, value)?;
+            0
         }
+    };
+
+    // Chunks left over from a previous, bigger value.
+    let mut stale = chunks;
+    while chunk_path(&file_path, stale).exists() {
+        fs::remove_file(chunk_path(&file_path, stale))?;
+        stale += 1;
+    }
+
+    Ok((file_path, chunks))
+}
+
+fn chunk_path(file_path: &Path, chunk: u32) -> PathBuf {
+    PathBuf::from(format!("{}.{}", file_path.display(), chunk))
+}
+
+const ZSTD_HEADER: u8 = 1;
+const LZ4_HEADER: u8 = 2;
+
+/// Compresses `value` with `compression`, prefixed by the codec byte.
+/// Returns `None` if no compression is requested.
+fn compress(value: &[u8], compression: Compression) -> io::Result<Option<Vec<u8>>> {
+    let (header, compressed) = match compression {
+        Compression::None => return Ok(None),
+        Compression::Zstd => (ZSTD_HEADER, zstd::encode_all(value, 0)?),
+        Compression::Lz4 => (LZ4_HEADER, lz4_flex::compress_prepend_size(value)),
+    };
+
+    let mut buff = Vec::with_capacity(compressed.len() + 1);
+    buff.push(header);
+    buff.extend_from_slice(&compressed);
+    Ok(Some(buff))
+}
 
-        Ok(ds)
+/// Decompresses a value written by `compress`, using the codec named by its first byte.
+fn decompress(buff: &[u8]) -> io::Result<Vec<u8>> {
+    match buff.split_first() {
+        Some((&ZSTD_HEADER, v)) => zstd::decode_all(v),
+        Some((&LZ4_HEADER, v)) => lz4_flex::decompress_size_prepended(v)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unknown compression header",
+        )),
     }
 }
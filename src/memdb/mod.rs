@@ -1 +1,2 @@
+mod lru;
 pub mod memory_database;
@@ -3,15 +3,20 @@ use chrono::prelude::DateTime;
 use chrono::Utc;
 use number_prefix::NumberPrefix;
 use number_prefix::NumberPrefix::{Prefixed, Standalone};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[cfg(target_os = "linux")]
 use std::os::unix::fs::OpenOptionsExt;
 #[cfg(target_os = "linux")]
 use std::fs::{OpenOptions};
 
-use std::fs::{File};
+use std::fs::{self, File};
+use std::hash::Hasher;
 use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use twox_hash::XxHash64;
 
 pub fn get_nano_time() -> u128 {
     SystemTime::now()
@@ -20,11 +25,81 @@ pub fn get_nano_time() -> u128 {
         .as_nanos()
 }
 
+/// Formats nanoseconds since the unix epoch as UTC date and time.
+/// Times beyond the dates chrono can represent are printed as plain nanoseconds.
 pub fn nano_time_fmt(nano: u128) -> String {
-    let d = UNIX_EPOCH + Duration::from_nanos(nano as u64);
-    let datetime = DateTime::<Utc>::from(d);
-    // Formats the combined date and time with the specified format string.
-    datetime.format("%Y-%m-%d %H:%M:%S.%f").to_string()
+    let secs = i64::try_from(nano / 1_000_000_000).ok();
+    let subsec_nanos = (nano % 1_000_000_000) as u32;
+    match secs.and_then(|v| DateTime::<Utc>::from_timestamp(v, subsec_nanos)) {
+        // Formats the combined date and time with the specified format string.
+        Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S.%f").to_string(),
+        None => format!("{}ns", nano),
+    }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = XxHash64::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Hashes the concatenated content of `paths` like `hash_bytes`,
+/// without loading the files into memory at once.
+pub fn hash_files<P: AsRef<Path>>(paths: &[P]) -> io::Result<u64> {
+    let mut hasher = XxHash64::default();
+    let mut buff = vec![0u8; 64 * 1024];
+    for path in paths {
+        let mut file = File::open(path)?;
+        loop {
+            let read = file.read(&mut buff)?;
+            if read == 0 {
+                break;
+            }
+            hasher.write(&buff[..read]);
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Folder name for `key`, safe to join to the cache path.
+/// The key is percent-encoded, keeping ASCII alphanumerics, `-`, `_` and `.`,
+/// so every key is a single folder that can't leave the cache path or contain the folder of another key.
+/// An empty key becomes `%`, `.` and `..` are encoded, neither can be produced otherwise.
+pub fn encode_key_path(key: &str) -> String {
+    match key {
+        "" => String::from("%"),
+        "." => String::from("%2E"),
+        ".." => String::from("%2E%2E"),
+        _ => key
+            .bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                    char::from(b).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect(),
+    }
+}
+
+/// Reverses `encode_key_path`, `None` if `path` isn't an encoded key.
+pub fn decode_key_path(path: &str) -> Option<String> {
+    if path == "%" {
+        return Some(String::new());
+    }
+    let mut bytes = vec![];
+    let mut chars = path.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'%' => {
+                let hex = [chars.next()?, chars.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'/' => return None,
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
 }
 
 pub fn fmt_bytes(b: u64) -> String {
@@ -34,32 +109,231 @@ pub fn fmt_bytes(b: u64) -> String {
     }
 }
 
+/// Like `fmt_bytes`, with decimal prefixes (base 1000).
+pub fn fmt_bytes_si(b: u64) -> String {
+    match NumberPrefix::decimal(b as f64) {
+        Standalone(bytes) => format!("{} bytes", bytes),
+        Prefixed(prefix, n) => format!("{:.0} {}B", n, prefix),
+    }
+}
+
 #[cfg(target_os = "linux")]
-pub fn get_non_buffered_file_handle(file_path: &str) -> io::Result<File>{
+pub fn get_non_buffered_file_handle<P: AsRef<Path>>(file_path: P) -> io::Result<File>{
      OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .custom_flags(libc::O_DIRECT)
             .open(&file_path)
 }
 
+/// Writes `data` to `file_path` through a non buffered handle and syncs it.
+/// `O_DIRECT` needs block aligned buffers and lengths, which most values aren't,
+/// so if the filesystem rejects the write with `EINVAL` the file is rewritten buffered.
+#[cfg(target_os = "linux")]
+pub fn write_non_buffered<P: AsRef<Path>>(file_path: P, data: &[u8]) -> io::Result<()> {
+    let direct = get_non_buffered_file_handle(&file_path).and_then(|f| write_synced(f, data));
+    match direct {
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            write_synced(File::create(&file_path)?, data)
+        }
+        v => v,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn write_non_buffered<P: AsRef<Path>>(file_path: P, data: &[u8]) -> io::Result<()> {
+    write_synced(get_non_buffered_file_handle(&file_path)?, data)
+}
+
+fn write_synced(mut file: File, data: &[u8]) -> io::Result<()> {
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// Writes `data` to `{file_path}.tmp` like `write_non_buffered` and renames it to `file_path`.
+/// The rename is atomic on the same filesystem, so readers see the old or the new file,
+/// never a partially written one.
+pub fn write_atomic<P: AsRef<Path>>(file_path: P, data: &[u8]) -> io::Result<()> {
+    let file_path = file_path.as_ref();
+    let mut tmp_path = file_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    write_non_buffered(&tmp_path, data)?;
+    fs::rename(&tmp_path, file_path)
+}
+
+#[cfg(target_os = "linux")]
+pub fn get_non_buffered_read_handle<P: AsRef<Path>>(file_path: P) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(&file_path)
+}
+
+#[cfg(target_os = "macos")]
+pub fn get_non_buffered_read_handle<P: AsRef<Path>>(file_path: P) -> io::Result<File> {
+    let file = File::open(&file_path)?;
+    rustix::fs::fcntl_nocache(&file, true)?;
+    Ok(file)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn get_non_buffered_read_handle<P: AsRef<Path>>(file_path: P) -> io::Result<File> {
+    File::open(&file_path)
+}
+
+/// Block size `O_DIRECT` reads are aligned to, in memory and in the file.
+#[cfg(target_os = "linux")]
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// Reads the whole file at `file_path` through a non buffered handle.
+/// `O_DIRECT` reads go into a block aligned part of the buffer in block sized requests,
+/// if the filesystem rejects them with `EINVAL` the file is read buffered.
+#[cfg(target_os = "linux")]
+pub fn read_non_buffered<P: AsRef<Path>>(file_path: P) -> io::Result<Vec<u8>> {
+    let direct = get_non_buffered_read_handle(&file_path).and_then(|mut file| {
+        let len = file.metadata()?.len() as usize;
+        let blocks = len / DIRECT_IO_ALIGNMENT + 1;
+        let mut buff = vec![0u8; (blocks + 1) * DIRECT_IO_ALIGNMENT];
+        let start = buff.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        let end = start + blocks * DIRECT_IO_ALIGNMENT;
+
+        let mut read = start;
+        while read < start + len {
+            match file.read(&mut buff[read..end])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        buff.truncate(read);
+        buff.drain(..start);
+        Ok(buff)
+    });
+    match direct {
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => fs::read(&file_path),
+        v => v,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_non_buffered<P: AsRef<Path>>(file_path: P) -> io::Result<Vec<u8>> {
+    let mut buff = vec![];
+    get_non_buffered_read_handle(&file_path)?.read_to_end(&mut buff)?;
+    Ok(buff)
+}
+
+/// Moves the folder `from` to `to`, creating the parents of `to`.
+/// Across filesystems, where it can't be renamed, its files are copied and `from` is removed.
+/// Only files directly in `from` are copied, like in the folders of cached items.
+pub fn move_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        if let Some(name) = path.file_name() {
+            fs::copy(&path, to.join(name))?;
+        }
+    }
+    fs::remove_dir_all(from)
+}
+
+/// Removes the empty folders from `folder` up to, but not including, `root`,
+/// e.g. the shard folders left behind once the last item in them is removed.
+/// Folders that are already gone are skipped,
+/// it stops at the first folder that isn't empty or can't be removed.
+pub fn remove_empty_parents<P: AsRef<Path>, Q: AsRef<Path>>(folder: P, root: Q) {
+    let root = root.as_ref();
+    let mut folder = Some(folder.as_ref());
+    while let Some(v) = folder {
+        if v == root || !v.starts_with(root) {
+            break;
+        }
+        match fs::remove_dir(v) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => break,
+            _ => folder = v.parent(),
+        }
+    }
+}
+
 #[cfg(target_os = "windows")]
-pub fn get_non_buffered_file_handle(file_path: &str) -> io::Result<File>{
+pub fn get_non_buffered_file_handle<P: AsRef<Path>>(file_path: P) -> io::Result<File>{
+    File::create(&file_path)
+}
+
+/// macOS has no `O_DIRECT`, `F_NOCACHE` turns off the page cache for the file instead.
+#[cfg(target_os = "macos")]
+pub fn get_non_buffered_file_handle<P: AsRef<Path>>(file_path: P) -> io::Result<File>{
+    let file = File::create(&file_path)?;
+    rustix::fs::fcntl_nocache(&file, true)?;
+    Ok(file)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+pub fn get_non_buffered_file_handle<P: AsRef<Path>>(file_path: P) -> io::Result<File>{
     File::create(&file_path)
 }
 
 pub mod logger {
     use colored::Colorize;
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::RwLock;
 
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    #[repr(u8)]
     pub enum Severity {
+        TRACE,
         DEBUG,
         LOG,
         WARN,
         ERROR,
     }
 
-    fn l(log_obj: &str, severity: &Severity) {
+    pub type Sink = Box<dyn Fn(Severity, &str) + Send + Sync>;
+
+    static LEVEL: AtomicU8 = AtomicU8::new(Severity::LOG as u8);
+    static SINK: RwLock<Option<Sink>> = RwLock::new(None);
+    static KEY_FILTER: RwLock<Option<String>> = RwLock::new(None);
+
+    /// Suppresses messages below `severity`. [Default: LOG]
+    pub fn set_level(severity: Severity) {
+        LEVEL.store(severity as u8, Ordering::Relaxed);
+    }
+
+    /// Whether messages of `severity` are printed.
+    /// Lets hot loops skip formatting messages that would be dropped.
+    pub fn enabled(severity: Severity) -> bool {
+        severity as u8 >= LEVEL.load(Ordering::Relaxed)
+    }
+
+    /// Sends all messages that pass the level to `sink` instead of stdout.
+    /// The sink is called from whichever thread logs, including the management threadpool.
+    pub fn set_sink(sink: Sink) {
+        *SINK.write().unwrap_or_else(|e| e.into_inner()) = Some(sink);
+    }
+
+    /// Drops messages logged for other keys than `key`, see `keyed`.
+    /// Messages without a key are unaffected. `None` prints all keys. [Default: None]
+    pub fn set_key_filter(key: Option<String>) {
+        *KEY_FILTER.write().unwrap_or_else(|e| e.into_inner()) = key;
+    }
+
+    /// Prints messages to stdout again, see `stdout_sink`.
+    pub fn reset_sink() {
+        *SINK.write().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// The default sink, printing colored messages to stdout.
+    pub fn stdout_sink(severity: Severity, log_obj: &str) {
         match severity {
+            Severity::TRACE => println!("{} {}", "[.]".dimmed(), log_obj.dimmed()),
             Severity::DEBUG => println!("{} {}", "[-]".green(), log_obj),
             Severity::LOG => println!("{} {}", "[+]".white(), log_obj),
             Severity::WARN => println!("{} {}", "[*]".yellow().bold(), log_obj),
@@ -67,16 +341,44 @@ pub mod logger {
         }
     }
 
+    fn l(log_obj: &str, severity: &Severity, key: Option<&str>) {
+        if !enabled(*severity) {
+            return;
+        }
+        let log_obj = match key {
+            Some(key) => {
+                let filter = KEY_FILTER.read().unwrap_or_else(|e| e.into_inner());
+                if filter.as_ref().is_some_and(|v| v != key) {
+                    return;
+                }
+                format!("{:?}: {}", key, log_obj)
+            }
+            None => log_obj.to_owned(),
+        };
+        match SINK.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(sink) => sink(*severity, &log_obj),
+            None => stdout_sink(*severity, &log_obj),
+        }
+    }
+
+    /// Logs `log_obj` about the item `key`, prefixed with the key and subject to `set_key_filter`.
+    pub fn keyed(severity: Severity, key: &str, log_obj: &str) {
+        l(log_obj, &severity, Some(key));
+    }
+
+    pub fn trace(log_obj: &str) {
+        l(log_obj, &Severity::TRACE, None);
+    }
     pub fn debug(log_obj: &str) {
-        l(log_obj, &Severity::DEBUG);
+        l(log_obj, &Severity::DEBUG, None);
     }
     pub fn log(log_obj: &str) {
-        l(log_obj, &Severity::LOG);
+        l(log_obj, &Severity::LOG, None);
     }
     pub fn warn(log_obj: &str) {
-        l(log_obj, &Severity::WARN);
+        l(log_obj, &Severity::WARN, None);
     }
     pub fn error(log_obj: &str) {
-        l(log_obj, &Severity::ERROR);
+        l(log_obj, &Severity::ERROR, None);
     }
 }
@@ -5,13 +5,39 @@ mod tests {
     use number_prefix::NumberPrefix;
     use number_prefix::NumberPrefix::{Prefixed, Standalone};
     use rayon::prelude::*;
-    use std::time::{Instant, SystemTime};
+    use std::io;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime};
     use xorshift::{
         Rand, Rng, RngJump, SeedableRng, SplitMix64, Xoroshiro128, Xorshift1024, Xorshift128,
     };
-    use rust_fast_cache::tools::{logger, fmt_bytes, get_nano_time};
-    use rust_fast_cache::cache_service::cache::{Cache, ONE_MEBIBYTE};
-    use rust_fast_cache::memdb::memory_database::{FastDB, DatabaseItem};
+    use rust_fast_cache::cache_service::backing_store::BackingStore;
+    use rust_fast_cache::cache_service::error::CacheError;
+    use rust_fast_cache::cache_service::typed_cache::{Codec, TypedCache};
+    use rust_fast_cache::tools::{
+        decode_key_path, encode_key_path, logger, fmt_bytes, fmt_bytes_si, get_nano_time,
+        nano_time_fmt, read_non_buffered,
+    };
+    use rust_fast_cache::cache_service::cache::{
+        Cache, CleanseStrategy, Compression, DiskLayout, TimeResolution, ONE_GIBIBYTE,
+        ONE_KIBIBYTE, ONE_MEBIBYTE,
+    };
+    use rust_fast_cache::memdb::memory_database::{
+        entry_overhead, DatabaseItem, FastDB, ENTRY_OVERHEAD,
+    };
+
+    /// Held by tests changing the global logger settings.
+    static LOGGER_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn test_cache_at(name: &str) -> Cache {
+        let path = std::env::temp_dir().join(format!("rust_fast_cache_{}", name));
+        let _ = std::fs::remove_dir_all(&path);
+        let mut cache_service = Cache::default();
+        cache_service.set_cache_path(String::from(path.to_str().unwrap()), false).unwrap();
+        cache_service
+    }
 
     #[test]
     fn test_cache() {
@@ -20,7 +46,7 @@ mod tests {
         let mut cache_service: Cache = Cache::default();
 
         cache_service.insert_cache_item(String::from("TEST"), vec![0, 1, 2]);
-        cache_service.insert_cache_item(String::from("TEST"), (0..255).map(u8::from).collect());
+        cache_service.insert_cache_item(String::from("TEST"), (0..255).map(u8::from).collect::<Vec<u8>>());
 
         cache_service.remove_cache_item("TEST");
 
@@ -62,17 +88,17 @@ mod tests {
         //Cache lookup test
         let now = Instant::now();
         let t10 = cache_service
-            .get_cache_value(String::from("TEST_1"))
+            .get_cache_value("TEST_1")
             .expect("Err");
         logger::debug(&format!("Len {:?}", t10.unwrap().len()));
         let mem_elapsed = now.elapsed();
         logger::debug(&format!("Elapsed {:?}", mem_elapsed));
 
-        cache_service.resize_cache(Some(ONE_MEBIBYTE), None, None);
+        cache_service.resize_cache(Some(ONE_MEBIBYTE), None, None).unwrap();
 
         let now = Instant::now();
         let t10 = cache_service
-            .get_cache_value(String::from("TEST_1"))
+            .get_cache_value("TEST_1")
             .expect("Err");
         logger::debug(&format!("Len {:?}", t10.unwrap().len()));
         let disk_elapsed = now.elapsed();
@@ -82,7 +108,7 @@ mod tests {
             disk_elapsed.as_nanos() as f64 / mem_elapsed.as_nanos() as f64
         ));
 
-        cache_service.resize_cache(Some(ONE_MEBIBYTE), Some(ONE_MEBIBYTE * 50), None);
+        cache_service.resize_cache(Some(ONE_MEBIBYTE), Some(ONE_MEBIBYTE * 50), None).unwrap();
         logger::log(&format!("{:?}", cache_service));
 
         logger::error(&format!("Finished cache testing in {:?}", nowx.elapsed()));
@@ -94,17 +120,23 @@ mod tests {
         memdb.set(
             String::from("test"),
             DatabaseItem {
-                value: Some(vec![0, 1]),
+                value: Some(Arc::new(vec![0, 1])),
                 last_access: get_nano_time(),
+                created_at: get_nano_time(),
                 access_counter: 0,
                 filepath: None,
+                value_hash: None,
+                boosted_until: None,
+                chunks: 0,
+                compressed: false,
+                ttl: None,
             },
         );
 
         let xvec = memdb.get("test").unwrap().unwrap();
         let xvec_val = xvec.value.unwrap();
         assert_eq!(xvec_val.len(), 2);
-        assert_eq!(xvec_val, vec![0, 1]);
+        assert_eq!(*xvec_val, vec![0, 1]);
 
         memdb.del("test").unwrap().unwrap();
 
@@ -120,10 +152,16 @@ mod tests {
             memdb.set(
                 format!("{}", i + max_i_1024),
                 DatabaseItem {
-                    value: Some((0..255).map(u8::from).collect()),
+                    value: Some(Arc::new((0..255).map(u8::from).collect())),
                     last_access: get_nano_time(),
+                    created_at: get_nano_time(),
                     access_counter: 0,
                     filepath: None,
+                    value_hash: None,
+                    boosted_until: None,
+                    chunks: 0,
+                    compressed: false,
+                    ttl: None,
                 },
             );
         }
@@ -136,10 +174,16 @@ mod tests {
             memdb.set(
                 format!("{}", i + max_i_4096),
                 DatabaseItem {
-                    value: Some((0..255).map(u8::from).collect()),
+                    value: Some(Arc::new((0..255).map(u8::from).collect())),
                     last_access: get_nano_time(),
+                    created_at: get_nano_time(),
                     access_counter: 0,
                     filepath: None,
+                    value_hash: None,
+                    boosted_until: None,
+                    chunks: 0,
+                    compressed: false,
+                    ttl: None,
                 },
             );
         }
@@ -152,15 +196,33 @@ mod tests {
             memdb.set(
                 format!("{}", i + max_i_16384),
                 DatabaseItem {
-                    value: Some((0..255).map(u8::from).collect()),
+                    value: Some(Arc::new((0..255).map(u8::from).collect())),
                     last_access: get_nano_time(),
+                    created_at: get_nano_time(),
                     access_counter: 0,
                     filepath: None,
+                    value_hash: None,
+                    boosted_until: None,
+                    chunks: 0,
+                    compressed: false,
+                    ttl: None,
                 },
             );
         }
         let elapsed_16384 = now.elapsed().unwrap();
 
+        let mut memdb = FastDB::with_capacity(max_i_16384 as usize);
+        let capacity = memdb.capacity();
+        assert!(capacity >= max_i_16384 as usize);
+        let now = SystemTime::now();
+        for i in 0..max_i_16384 {
+            memdb.set(
+                format!("{}", i + max_i_16384),
+                DatabaseItem::new((0..255).map(u8::from).collect()),
+            );
+        }
+        let elapsed_16384_presized = now.elapsed().unwrap();
+
         logger::log(&format!(
             "{:?}:\t{:?} ({:?} ns/insert)",
             max_i_1024,
@@ -179,5 +241,2750 @@ mod tests {
             elapsed_16384,
             elapsed_16384.as_nanos() as f64 / max_i_16384 as f64
         ));
+        logger::log(&format!(
+            "{:?} presized:\t{:?} ({:?} ns/insert, capacity {} -> {})",
+            max_i_16384,
+            elapsed_16384_presized,
+            elapsed_16384_presized.as_nanos() as f64 / max_i_16384 as f64,
+            capacity,
+            memdb.capacity()
+        ));
+    }
+
+    #[test]
+    fn test_dedup_identical() {
+        let mut cache_service = test_cache_at("dedup_identical");
+        cache_service.set_dedup_identical(true);
+
+        let value: Vec<u8> = (0..255).collect();
+        cache_service.insert_cache_item(String::from("DEDUP"), value.clone()).unwrap();
+        for _ in 0..5 {
+            cache_service.get_cache_item("DEDUP").unwrap();
+        }
+        let before = cache_service.get_cache_item("DEDUP").unwrap().unwrap();
+        assert_eq!(before.access_counter, 6);
+
+        std::thread::sleep(Duration::from_millis(1));
+        cache_service.insert_cache_item(String::from("DEDUP"), value.clone()).unwrap();
+        // The skipped insert renews the access, without counting one.
+        let (_, last_access, access_counter, _, _) = cache_service.iter_metadata().remove(0);
+        assert!(last_access > before.last_access);
+        assert_eq!(access_counter, 6);
+
+        let after = cache_service.get_cache_item("DEDUP").unwrap().unwrap();
+        assert_eq!(after.access_counter, 7);
+        assert_eq!(*after.value.unwrap(), value);
+
+        cache_service.insert_cache_item(String::from("DEDUP"), vec![0, 1, 2]).unwrap();
+        let changed = cache_service.get_cache_item("DEDUP").unwrap().unwrap();
+        assert_eq!(changed.access_counter, 1);
+        assert_eq!(*changed.value.unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_replicate_to() {
+        let mut source = test_cache_at("replicate_source");
+        for i in 0..10 {
+            source
+                .insert_cache_item(format!("REPL_{}", i), vec![i as u8; 1024])
+                .unwrap();
+        }
+        source.resize_cache(Some(ONE_KIBIBYTE * 4), None, None).unwrap();
+        let on_disk = (0..10)
+            .filter(|i| {
+                let item = source.get_cache_item(&format!("REPL_{}", i)).unwrap().unwrap();
+                item.filepath.is_some()
+            })
+            .count();
+        assert!(on_disk > 0);
+
+        let mut dest = test_cache_at("replicate_dest");
+        assert_eq!(source.replicate_to(&mut dest).unwrap(), 10);
+
+        for i in 0..10 {
+            let value = dest.get_cache_value(&format!("REPL_{}", i)).unwrap();
+            assert_eq!(value, Some(vec![i as u8; 1024]));
+        }
+    }
+
+    #[test]
+    fn test_write_through() {
+        let mut cache_service = test_cache_at("write_through");
+        let value: Vec<u8> = (0..255).collect();
+        cache_service
+            .insert_write_through(String::from("WT"), value.clone())
+            .unwrap();
+
+        let item = cache_service.get_cache_item("WT").unwrap().unwrap();
+        assert_eq!(item.value, Some(Arc::new(value.clone())));
+        assert!(item.filepath.unwrap().exists());
+
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+
+        let item = cache_service.get_cache_item("WT").unwrap().unwrap();
+        assert!(item.value.is_none());
+        assert!(item.filepath.is_some());
+        assert_eq!(
+            cache_service.get_cache_value("WT").unwrap(),
+            Some(value)
+        );
+
+        cache_service
+            .insert_write_through(String::from("WT"), vec![0, 1, 2])
+            .unwrap();
+        assert_eq!(
+            cache_service.get_cache_value("WT").unwrap(),
+            Some(vec![0, 1, 2])
+        );
+    }
+
+    #[test]
+    fn test_eviction_shortfall() {
+        let mut cache_service = test_cache_at("eviction_shortfall");
+        for i in 0..5 {
+            cache_service
+                .insert_cache_item(format!("SHORT_{}", i), vec![0; 1024])
+                .unwrap();
+        }
+        cache_service.resize_cache(Some(ONE_KIBIBYTE * 64), None, None).unwrap();
+        assert_eq!(cache_service.stats().eviction_shortfall_bytes, 0);
+
+        // Everything is moved to disk, but the item headers stay in memory.
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        assert!(cache_service.memory_used() > 0);
+        assert_eq!(
+            cache_service.stats().eviction_shortfall_bytes,
+            cache_service.memory_used()
+        );
+        assert!(cache_service.disk_used() >= 5 * 1024);
+    }
+
+    #[test]
+    fn test_get_full() {
+        let mut cache_service = test_cache_at("get_full");
+        cache_service.insert_cache_item(String::from("FULL"), vec![0, 1, 2]).unwrap();
+        let before = cache_service.get_cache_item("FULL").unwrap().unwrap();
+
+        let (value, metadata) = cache_service.get_full("FULL").unwrap().unwrap();
+        assert_eq!(value, vec![0, 1, 2]);
+        assert_eq!(metadata.access_counter, before.access_counter + 1);
+        assert!(metadata.last_access >= before.last_access);
+        assert!(metadata.in_memory);
+
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        let (value, metadata) = cache_service.get_full("FULL").unwrap().unwrap();
+        assert_eq!(value, vec![0, 1, 2]);
+        assert_eq!(metadata.access_counter, before.access_counter + 2);
+        assert!(!metadata.in_memory);
+
+        assert!(cache_service.get_full("MISSING").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_spill_reuses_folder() {
+        let mut cache_service = test_cache_at("spill_reuses_folder");
+        let folder = std::env::temp_dir()
+            .join("rust_fast_cache_spill_reuses_folder")
+            .join("REUSE");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("cachefile"), vec![9; 4096]).unwrap();
+        std::fs::write(folder.join("marker"), b"marker").unwrap();
+
+        cache_service.insert_cache_item(String::from("REUSE"), vec![0, 1, 2]).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+
+        assert!(folder.join("marker").exists());
+        assert_eq!(std::fs::read(folder.join("cachefile")).unwrap(), vec![0, 1, 2]);
+        assert_eq!(
+            cache_service.get_cache_value("REUSE").unwrap(),
+            Some(vec![0, 1, 2])
+        );
+
+        // A key without a folder yet. The disk isn't injectable, so the calls to create
+        // and remove folders can't be counted; the spill may only add the folder of the key.
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_spill_reuses_folder");
+        let sibling = cache_path.join("sibling");
+        std::fs::write(&sibling, b"sibling").unwrap();
+        let written = std::fs::metadata(&sibling).unwrap().modified().unwrap();
+        let fresh = cache_path.join("FRESH");
+        assert!(!fresh.exists());
+
+        let entries = |path: &Path| {
+            let mut v: Vec<_> = std::fs::read_dir(path)
+                .unwrap()
+                .map(|v| v.unwrap().file_name())
+                .collect();
+            v.sort();
+            v
+        };
+        let before = entries(&cache_path);
+
+        cache_service.resize_cache(Some(ONE_GIBIBYTE), None, None).unwrap();
+        cache_service.insert_cache_item(String::from("FRESH"), vec![3; 16]).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        assert_eq!(std::fs::read(fresh.join("cachefile")).unwrap(), vec![3; 16]);
+        assert_eq!(entries(&fresh), vec![std::ffi::OsString::from("cachefile")]);
+        let mut expected = before;
+        expected.push(std::ffi::OsString::from("FRESH"));
+        expected.sort();
+        assert_eq!(entries(&cache_path), expected);
+
+        assert_eq!(std::fs::read(&sibling).unwrap(), b"sibling");
+        assert_eq!(std::fs::metadata(&sibling).unwrap().modified().unwrap(), written);
+    }
+
+    #[test]
+    fn test_eviction_candidates() {
+        let strategies = [
+            CleanseStrategy::LastAccess,
+            CleanseStrategy::LeastUsed,
+            CleanseStrategy::Combined,
+        ];
+        for (n, strategy) in strategies.iter().enumerate() {
+            let name = format!("eviction_candidates_{}", n);
+            let mut cache_service = test_cache_at(&name);
+            for i in 0..4 {
+                let key = format!("CAND_{}", i);
+                cache_service.insert_cache_item(key.clone(), vec![0; 256]).unwrap();
+                for _ in 0..(4 - i) {
+                    cache_service.get_cache_item(&key).unwrap();
+                }
+            }
+            cache_service.resize_cache(Some(ONE_GIBIBYTE), None, Some(*strategy)).unwrap();
+
+            let candidates = cache_service.eviction_candidates(4);
+            assert_eq!(candidates.len(), 4);
+            assert_eq!(cache_service.eviction_candidates(2), candidates[..2].to_vec());
+
+            let cache_path = std::env::temp_dir().join(format!("rust_fast_cache_{}", name));
+            for evicted in 0..candidates.len() {
+                let new_max = cache_service.memory_used() - 1;
+                cache_service.resize_cache(Some(new_max), None, Some(*strategy)).unwrap();
+                for (i, key) in candidates.iter().enumerate() {
+                    assert_eq!(cache_path.join(key).exists(), i <= evicted);
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct StubBackingStore {
+        fetches: AtomicUsize,
+        stored: std::sync::Mutex<Vec<(String, Vec<u8>)>>,
+    }
+
+    impl BackingStore for StubBackingStore {
+        fn fetch(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            if key.starts_with("BACKED") {
+                Ok(Some(key.as_bytes().to_vec()))
+            } else if key.starts_with("FAILING") {
+                Err(io::Error::new(io::ErrorKind::ConnectionRefused, "store unavailable").into())
+            } else {
+                Ok(None)
+            }
+        }
+
+        fn store(&self, key: &str, value: &[u8]) -> Result<(), CacheError> {
+            self.stored.lock().unwrap().push((key.to_owned(), value.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_backing_store() {
+        let mut cache_service = test_cache_at("backing_store");
+        let backing_store = Arc::new(StubBackingStore::default());
+        cache_service.set_backing_store(backing_store.clone());
+
+        let value = cache_service.get_cache_value("BACKED_1").unwrap();
+        assert_eq!(value, Some(b"BACKED_1".to_vec()));
+        assert_eq!(backing_store.fetches.load(Ordering::Relaxed), 1);
+
+        let value = cache_service.get_cache_value("BACKED_1").unwrap();
+        assert_eq!(value, Some(b"BACKED_1".to_vec()));
+        assert_eq!(backing_store.fetches.load(Ordering::Relaxed), 1);
+
+        assert!(cache_service.get_cache_value("NOT_BACKED").unwrap().is_none());
+        assert!(cache_service.get_cache_item("NOT_BACKED").unwrap().is_none());
+        assert_eq!(backing_store.fetches.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_remap_values() {
+        let mut cache_service = test_cache_at("remap_values");
+        for i in 0..4 {
+            cache_service
+                .insert_cache_item(format!("REMAP_{}", i), vec![i as u8; 1024])
+                .unwrap();
+        }
+        cache_service.resize_cache(Some(cache_service.memory_used() - 2048), None, None).unwrap();
+
+        let on_disk = (0..4)
+            .filter(|i| {
+                std::env::temp_dir()
+                    .join("rust_fast_cache_remap_values")
+                    .join(format!("REMAP_{}", i))
+                    .exists()
+            })
+            .count() as u64;
+        assert!(on_disk > 0 && on_disk < 4);
+
+        let memory_used = cache_service.memory_used();
+        let disk_used = cache_service.disk_used();
+
+        cache_service
+            .remap_values(|_, mut value| {
+                value.truncate(value.len() / 2);
+                value
+            })
+            .unwrap();
+
+        assert_eq!(cache_service.memory_used(), memory_used - (4 - on_disk) * 512);
+        assert_eq!(cache_service.disk_used(), disk_used - on_disk * 512);
+        for i in 0..4 {
+            let value = cache_service.get_cache_value(&format!("REMAP_{}", i)).unwrap();
+            assert_eq!(value, Some(vec![i as u8; 512]));
+        }
+    }
+
+    #[test]
+    fn test_created_at() {
+        let mut cache_service = test_cache_at("created_at");
+        cache_service.insert_cache_item(String::from("CREATED"), vec![0, 1, 2]).unwrap();
+        let first = cache_service.get_cache_item("CREATED").unwrap().unwrap();
+
+        std::thread::sleep(Duration::from_millis(5));
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        let second = cache_service.get_cache_item("CREATED").unwrap().unwrap();
+
+        assert!(second.value.is_none());
+        assert_eq!(second.created_at, first.created_at);
+        assert!(second.last_access > first.last_access);
+        assert!(first.last_access >= first.created_at);
+    }
+
+    #[test]
+    fn test_instrument() {
+        let mut cache_service = test_cache_at("instrument");
+        cache_service.insert_cache_item(String::from("UNTIMED"), vec![0]).unwrap();
+        assert_eq!(cache_service.stats().insert_timings.count, 0);
+
+        cache_service.set_instrument(true);
+        for i in 0..4 {
+            cache_service
+                .insert_cache_item(format!("TIMED_{}", i), vec![0; 1024])
+                .unwrap();
+            cache_service.get_cache_value(&format!("TIMED_{}", i)).unwrap();
+        }
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        cache_service.get_cache_value("TIMED_0").unwrap();
+        cache_service.remove_cache_item("TIMED_1").unwrap();
+
+        let stats = cache_service.stats();
+        for (timings, count) in &[
+            (stats.insert_timings, 4),
+            (stats.get_memory_timings, 4),
+            (stats.get_disk_timings, 1),
+            (stats.resize_timings, 1),
+            (stats.remove_timings, 1),
+        ] {
+            assert_eq!(timings.count, *count);
+            assert!(timings.min_nanos > 0);
+            assert!(timings.min_nanos <= timings.avg_nanos);
+            assert!(timings.avg_nanos <= timings.max_nanos);
+        }
+    }
+
+    #[test]
+    fn test_index_overhead() {
+        let mut cache_service = test_cache_at("index_overhead");
+        for i in 0..1000 {
+            cache_service
+                .insert_cache_item(format!("OVERHEAD_{}", i), vec![0])
+                .unwrap();
+        }
+
+        let overhead = cache_service.index_overhead_bytes();
+        let memory_used = cache_service.memory_used();
+        assert!(overhead > 0);
+        // The map's memory for each item is part of `memory_used` already.
+        assert!(overhead < memory_used);
+
+        cache_service.set_count_index_overhead(true);
+        assert_eq!(cache_service.memory_used(), memory_used + overhead);
+
+        // The values alone fit, so only the overhead makes items move to disk.
+        assert_eq!(cache_service.disk_used(), 0);
+        cache_service.resize_cache(Some(memory_used), None, None).unwrap();
+        assert!(cache_service.disk_used() > 0);
+    }
+
+    #[test]
+    fn test_flush_cold() {
+        let mut cache_service = test_cache_at("flush_cold");
+        for i in 0..3 {
+            cache_service
+                .insert_cache_item(format!("COLD_{}", i), vec![0; 1024])
+                .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        for i in 0..3 {
+            cache_service
+                .insert_cache_item(format!("HOT_{}", i), vec![0; 1024])
+                .unwrap();
+        }
+        cache_service.get_cache_item("COLD_0").unwrap();
+
+        let memory_used = cache_service.memory_used();
+        let moved = cache_service.flush_cold(Duration::from_millis(100)).unwrap();
+        assert_eq!(moved, 2 * 1024);
+        assert_eq!(cache_service.disk_used(), 2 * 1024);
+        let value_size = DatabaseItem::new(vec![0; 1024]).get_mem_size();
+        assert_eq!(cache_service.memory_used(), memory_used - 2 * value_size);
+
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_flush_cold");
+        assert!(!cache_path.join("COLD_0").exists());
+        assert!(cache_path.join("COLD_1").exists());
+        assert!(cache_path.join("COLD_2").exists());
+        for i in 0..3 {
+            assert!(!cache_path.join(format!("HOT_{}", i)).exists());
+        }
+    }
+
+    #[test]
+    fn test_least_used_tie_break() {
+        let items = vec![
+            ("A", 10, 5),
+            ("B", 10, 3),
+            ("C", 5, 9),
+            ("D", 10, 3),
+            ("E", 10, 3),
+        ];
+
+        for rotation in 0..items.len() {
+            let mut memdb = FastDB::default();
+            let mut rotated = items.clone();
+            rotated.rotate_left(rotation);
+            for (key, last_access, created_at) in rotated {
+                memdb
+                    .set(
+                        String::from(key),
+                        DatabaseItem {
+                            last_access,
+                            created_at,
+                            ..DatabaseItem::new(vec![0])
+                        },
+                    )
+                    .unwrap();
+            }
+
+            assert_eq!(
+                memdb.eviction_candidates(&CleanseStrategy::LeastUsed, 5),
+                vec!["C", "B", "D", "E", "A"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_boost() {
+        let mut cache_service = test_cache_at("boost");
+        for key in &["BOOST_COLD", "BOOST_A", "BOOST_B"] {
+            cache_service.insert_cache_item(String::from(*key), vec![0; 1024]).unwrap();
+        }
+        cache_service.resize_cache(Some(ONE_GIBIBYTE), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        cache_service.boost("BOOST_COLD", Instant::now() + Duration::from_millis(200));
+
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_boost");
+        let new_max = cache_service.memory_used() - 1;
+        cache_service.resize_cache(Some(new_max), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        assert!(!cache_path.join("BOOST_COLD").exists());
+        assert!(cache_path.join("BOOST_A").exists());
+
+        std::thread::sleep(Duration::from_millis(300));
+        let new_max = cache_service.memory_used() - 1;
+        cache_service.resize_cache(Some(new_max), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        assert!(cache_path.join("BOOST_COLD").exists());
+        assert!(!cache_path.join("BOOST_B").exists());
+    }
+
+    #[test]
+    fn test_inline_management() {
+        let mut cache_service = test_cache_at("inline_management");
+        assert!(cache_service.management_threads() > 0);
+
+        cache_service.set_management_threads(0);
+        assert_eq!(cache_service.management_threads(), 0);
+
+        for i in 0..4 {
+            cache_service
+                .insert_cache_item(format!("INLINE_{}", i), vec![i as u8; 1024])
+                .unwrap();
+        }
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        for i in 0..4 {
+            let value = cache_service.get_cache_value(&format!("INLINE_{}", i)).unwrap();
+            assert_eq!(value, Some(vec![i as u8; 1024]));
+        }
+    }
+
+    #[test]
+    fn test_verify_all() {
+        let mut cache_service = test_cache_at("verify_all");
+        for key in &["VERIFY_GOOD", "VERIFY_CORRUPT", "VERIFY_MISSING"] {
+            cache_service.insert_cache_item(String::from(*key), vec![7; 1024]).unwrap();
+        }
+        cache_service.insert_cache_item(String::from("VERIFY_MEMORY"), vec![7; 1024]).unwrap();
+        let new_max = cache_service.memory_used() - 3 * 1024;
+        cache_service.resize_cache(Some(new_max), None, Some(CleanseStrategy::LastAccess)).unwrap();
+
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_verify_all");
+        std::fs::write(cache_path.join("VERIFY_CORRUPT").join("cachefile"), vec![8; 1024]).unwrap();
+        std::fs::remove_file(cache_path.join("VERIFY_MISSING").join("cachefile")).unwrap();
+
+        let report = cache_service.verify_all().unwrap();
+        assert_eq!(report.ok, vec!["VERIFY_GOOD"]);
+        assert_eq!(report.corrupt, vec!["VERIFY_CORRUPT"]);
+        assert_eq!(report.missing, vec!["VERIFY_MISSING"]);
+
+        cache_service.evict_failed(&report).unwrap();
+        assert!(cache_service.get_cache_item("VERIFY_CORRUPT").unwrap().is_none());
+        assert!(cache_service.get_cache_item("VERIFY_MISSING").unwrap().is_none());
+        assert!(cache_service.get_cache_item("VERIFY_GOOD").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_time_resolution() {
+        let mut cache_service = test_cache_at("time_resolution");
+        cache_service.set_time_resolution(TimeResolution::Coarse);
+        cache_service.insert_cache_item(String::from("CLOCK"), vec![0]).unwrap();
+
+        let first = cache_service.get_cache_item("CLOCK").unwrap().unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        let second = cache_service.get_cache_item("CLOCK").unwrap().unwrap();
+        assert_eq!(first.last_access, second.last_access);
+        assert_eq!(first.created_at, first.last_access);
+
+        cache_service.refresh_clock();
+        let third = cache_service.get_cache_item("CLOCK").unwrap().unwrap();
+        assert!(third.last_access > second.last_access);
+
+        cache_service.set_time_resolution(TimeResolution::Millis);
+        let fourth = cache_service.get_cache_item("CLOCK").unwrap().unwrap();
+        assert_eq!(fourth.last_access % 1_000_000, 0);
+    }
+
+    #[test]
+    fn test_time_resolution_speed() {
+        let mut clock_reads = vec![];
+        for resolution in &[TimeResolution::Nanos, TimeResolution::Coarse] {
+            let mut cache_service = test_cache_at("time_resolution_speed");
+            cache_service.set_time_resolution(*resolution);
+            cache_service.insert_cache_item(String::from("CLOCK"), vec![0]).unwrap();
+
+            let reads = cache_service.stats().clock_reads;
+            let now = Instant::now();
+            for _ in 0..100_000 {
+                cache_service.get_full("CLOCK").unwrap();
+            }
+            let reads = cache_service.stats().clock_reads - reads;
+            let elapsed = now.elapsed();
+            logger::log(&format!("{:?}: {} clock reads in {:?}", resolution, reads, elapsed));
+            clock_reads.push(reads);
+        }
+
+        assert!(clock_reads[0] >= 100_000);
+        assert_eq!(clock_reads[1], 0);
+    }
+
+    #[test]
+    fn test_get_or_compute_many() {
+        let mut cache_service = test_cache_at("get_or_compute_many");
+        cache_service.insert_cache_item(String::from("MANY_1"), vec![1]).unwrap();
+        cache_service.insert_cache_item(String::from("MANY_3"), vec![3]).unwrap();
+
+        let keys: Vec<String> = (0..5).map(|i| format!("MANY_{}", i)).collect();
+        let mut computed_for = vec![];
+        let values = cache_service
+            .get_or_compute_many(keys.clone(), |missing| {
+                computed_for = missing.to_vec();
+                missing
+                    .iter()
+                    .map(|k| vec![k[5..].parse::<u8>().unwrap()])
+                    .collect()
+            })
+            .unwrap();
+
+        assert_eq!(computed_for, vec!["MANY_0", "MANY_2", "MANY_4"]);
+        assert_eq!(values, vec![vec![0], vec![1], vec![2], vec![3], vec![4]]);
+
+        let values = cache_service
+            .get_or_compute_many(keys, |_| panic!("Everything is cached"))
+            .unwrap();
+        assert_eq!(values, vec![vec![0], vec![1], vec![2], vec![3], vec![4]]);
+    }
+
+    #[test]
+    fn test_promote_on_disk_evict() {
+        let mut cache_service = test_cache_at("promote_on_disk_evict");
+        cache_service.set_promote_on_disk_evict(true);
+        for key in &["PROMOTE_COLD", "PROMOTE_WARM", "PROMOTE_HOT"] {
+            cache_service.insert_cache_item(String::from(*key), vec![0; 1024]).unwrap();
+        }
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        cache_service.get_cache_item("PROMOTE_HOT").unwrap();
+        assert_eq!(cache_service.disk_used(), 3 * 1024);
+
+        cache_service.resize_cache(
+            Some(ONE_MEBIBYTE),
+            Some(2 * 1024),
+            Some(CleanseStrategy::LastAccess),
+        ).unwrap();
+        assert_eq!(cache_service.disk_used(), 2 * 1024);
+
+        let hot = cache_service.get_cache_item("PROMOTE_HOT").unwrap().unwrap();
+        assert_eq!(hot.value, Some(Arc::new(vec![0; 1024])));
+        assert!(hot.filepath.is_none());
+        for key in &["PROMOTE_COLD", "PROMOTE_WARM"] {
+            let item = cache_service.get_cache_item(key).unwrap().unwrap();
+            assert!(item.value.is_none());
+        }
+    }
+
+    #[test]
+    fn test_lock_contention_stats() {
+        let memdb = FastDB::default();
+        memdb.set_lock_contention_stats(true);
+
+        // Contention depends on scheduling, retry a few rounds before giving up.
+        for _ in 0..20 {
+            let handles: Vec<_> = (0..8)
+                .map(|t| {
+                    let mut memdb = memdb.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..2000 {
+                            memdb
+                                .set(
+                                    format!("{}_{}", t, i % 16),
+                                    DatabaseItem::new(vec![0; 1024]),
+                                )
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            if memdb.lock_contention().write_waits > 0 {
+                break;
+            }
+        }
+
+        let contention = memdb.lock_contention();
+        assert!(contention.write_waits > 0);
+        assert!(contention.write_wait_nanos > 0);
+
+        let mut cache_service = test_cache_at("lock_contention_stats");
+        assert_eq!(cache_service.stats().lock_contention.write_waits, 0);
+        cache_service.set_lock_contention_stats(true);
+        cache_service.insert_cache_item(String::from("LOCK"), vec![0]).unwrap();
+        assert_eq!(cache_service.stats().lock_contention.write_waits, 0);
+    }
+
+    #[test]
+    fn test_max_chunk_size() {
+        let mut cache_service = test_cache_at("max_chunk_size");
+        let folder = std::env::temp_dir()
+            .join("rust_fast_cache_max_chunk_size")
+            .join("CHUNKED");
+        let value: Vec<u8> = (0..2500).map(|v| (v % 251) as u8).collect();
+        cache_service.set_max_chunk_size(Some(1000));
+
+        cache_service.insert_cache_item(String::from("CHUNKED"), value.clone()).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+
+        assert!(!folder.join("cachefile").exists());
+        for i in 0..3 {
+            assert!(folder.join(format!("cachefile.{}", i)).exists());
+        }
+        assert!(!folder.join("cachefile.3").exists());
+        assert_eq!(cache_service.disk_used(), 2500);
+        assert_eq!(
+            cache_service.get_cache_value("CHUNKED").unwrap(),
+            Some(value)
+        );
+        assert_eq!(cache_service.verify_all().unwrap().ok, vec![String::from("CHUNKED")]);
+    }
+
+    #[test]
+    fn test_cleanup_disk() {
+        let mut cache_service = test_cache_at("cleanup_disk");
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_cleanup_disk");
+        for i in 0..4 {
+            cache_service
+                .insert_cache_item(format!("DISK_{}", i), vec![0; 1024])
+                .unwrap();
+        }
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        cache_service.resize_cache(Some(ONE_GIBIBYTE), None, None).unwrap();
+        cache_service.insert_cache_item(String::from("MEM"), vec![0; 1024]).unwrap();
+        assert_eq!(cache_service.disk_used(), 4096);
+
+        cache_service.resize_cache(
+            Some(ONE_GIBIBYTE),
+            Some(2048),
+            Some(CleanseStrategy::LastAccess),
+        ).unwrap();
+
+        assert_eq!(cache_service.disk_used(), 2048);
+        assert!(!cache_path.join("DISK_0").exists());
+        assert!(!cache_path.join("DISK_1").exists());
+        assert!(cache_path.join("DISK_2").exists());
+        assert!(cache_path.join("DISK_3").exists());
+        assert_eq!(cache_service.get_cache_value("DISK_0").unwrap(), None);
+        assert_eq!(
+            cache_service.get_cache_value("MEM").unwrap(),
+            Some(vec![0; 1024])
+        );
+    }
+
+    #[test]
+    fn test_expiry_sweeper() {
+        let mut memdb = FastDB::default();
+        let folder = std::env::temp_dir().join("rust_fast_cache_expiry_sweeper_memdb");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("cachefile"), vec![0; 1024]).unwrap();
+        let now = get_nano_time();
+        memdb
+            .set(
+                String::from("OLD"),
+                DatabaseItem {
+                    value: None,
+                    last_access: now - 10,
+                    filepath: Some(folder.join("cachefile")),
+                    ..DatabaseItem::default()
+                },
+            )
+            .unwrap();
+        memdb
+            .set(
+                String::from("NEW"),
+                DatabaseItem {
+                    last_access: now + 10,
+                    ..DatabaseItem::new(vec![0; 16])
+                },
+            )
+            .unwrap();
+
+        let (_, disk_freed) = memdb.remove_expired(now, 0);
+        assert_eq!(disk_freed, 1024);
+        assert!(!folder.exists());
+        assert_eq!(memdb.keys(), vec![String::from("NEW")]);
+
+        let mut cache_service = test_cache_at("expiry_sweeper");
+        cache_service.insert_cache_item(String::from("FRESH"), vec![0; 64]).unwrap();
+        let memory_used = cache_service.memory_used();
+        cache_service.start_expiry_sweeper(Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(20));
+        cache_service.stop_expiry_sweeper();
+        cache_service.stop_expiry_sweeper();
+
+        assert_eq!(cache_service.memory_used(), memory_used);
+        assert_eq!(
+            cache_service.get_cache_value("FRESH").unwrap(),
+            Some(vec![0; 64])
+        );
+    }
+
+    #[test]
+    fn test_remove_spilled_item() {
+        let mut cache_service = test_cache_at("remove_spilled_item");
+        cache_service.insert_cache_item(String::from("SPILLED"), vec![0; 4096]).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        assert!(cache_service.memory_used() > 0);
+
+        let removed = cache_service.remove_cache_item("SPILLED").unwrap().unwrap();
+        assert_eq!(removed.value.as_deref(), Some(&vec![0; 4096]));
+        assert_eq!(removed.filepath, None);
+        assert_eq!(cache_service.memory_used(), 0);
+
+        assert!(cache_service.remove_cache_item("SPILLED").unwrap().is_none());
+        assert_eq!(cache_service.memory_used(), 0);
+
+        cache_service.insert_cache_item(String::from("IN_MEMORY"), vec![1; 16]).unwrap();
+        let removed = cache_service.remove_cache_item("IN_MEMORY").unwrap().unwrap();
+        assert_eq!(removed.value.as_deref(), Some(&vec![1; 16]));
+    }
+
+    #[test]
+    fn test_len() {
+        let mut cache_service = test_cache_at("len");
+        assert!(cache_service.is_empty());
+
+        cache_service.insert_cache_item(String::from("LEN_0"), vec![0; 64]).unwrap();
+        cache_service.insert_cache_item(String::from("LEN_1"), vec![0; 64]).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        cache_service.insert_cache_item(String::from("LEN_1"), vec![1; 64]).unwrap();
+
+        assert_eq!(cache_service.len(), 2);
+        assert!(!cache_service.is_empty());
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut cache_service = test_cache_at("clear");
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_clear");
+        cache_service.clear().unwrap();
+
+        cache_service.insert_cache_item(String::from("CLEAR_0"), vec![0; 64]).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        cache_service.resize_cache(Some(ONE_GIBIBYTE), None, None).unwrap();
+        cache_service.insert_cache_item(String::from("CLEAR_1"), vec![0; 64]).unwrap();
+        std::fs::write(cache_path.join("unrelated"), b"unrelated").unwrap();
+        assert!(cache_path.join("CLEAR_0").exists());
+
+        cache_service.clear().unwrap();
+
+        assert!(cache_service.is_empty());
+        assert_eq!(cache_service.memory_used(), 0);
+        assert_eq!(cache_service.disk_used(), 0);
+        assert!(!cache_path.join("CLEAR_0").exists());
+        assert!(cache_path.join("unrelated").exists());
+        assert_eq!(cache_service.get_cache_value("CLEAR_1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_shared_values() {
+        let mut cache_service = test_cache_at("shared_values");
+        cache_service.insert_cache_item(String::from("SMALL"), vec![0; 64]).unwrap();
+        cache_service
+            .insert_cache_item(String::from("LARGE"), vec![0; 64 * ONE_MEBIBYTE as usize])
+            .unwrap();
+
+        let first = cache_service.get_cache_value_shared("LARGE").unwrap().unwrap();
+        let second = cache_service.get_cache_value_shared("LARGE").unwrap().unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let now = Instant::now();
+        for _ in 0..1000 {
+            cache_service.get_cache_value_shared("SMALL").unwrap();
+        }
+        let small_elapsed = now.elapsed();
+        let now = Instant::now();
+        for _ in 0..1000 {
+            cache_service.get_cache_value_shared("LARGE").unwrap();
+        }
+        let large_elapsed = now.elapsed();
+        logger::log(&format!("64B: {:?}, 64MiB: {:?}", small_elapsed, large_elapsed));
+    }
+
+    #[test]
+    fn test_contains_key() {
+        let mut cache_service = test_cache_at("contains_key");
+        cache_service.insert_cache_item(String::from("FIRST"), vec![0; 64]).unwrap();
+        cache_service.insert_cache_item(String::from("SECOND"), vec![0; 64]).unwrap();
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        let memory_used = cache_service.memory_used();
+
+        assert!(cache_service.contains_key("FIRST"));
+        assert!(!cache_service.contains_key("ABSENT"));
+
+        assert_eq!(
+            cache_service.eviction_candidates(2),
+            vec![String::from("FIRST"), String::from("SECOND")]
+        );
+        assert_eq!(cache_service.memory_used(), memory_used);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut cache_service = test_cache_at("peek");
+        cache_service.insert_cache_item(String::from("FIRST"), vec![1; 64]).unwrap();
+        cache_service.insert_cache_item(String::from("SECOND"), vec![2; 64]).unwrap();
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+
+        assert_eq!(cache_service.peek("FIRST").unwrap(), Some(vec![1; 64]));
+        assert_eq!(cache_service.peek("ABSENT").unwrap(), None);
+
+        assert_eq!(
+            cache_service.eviction_candidates(2),
+            vec![String::from("FIRST"), String::from("SECOND")]
+        );
+    }
+
+    #[test]
+    fn test_cache_error() {
+        let mut cache_service = test_cache_at("cache_error");
+        cache_service.set_backing_store(Arc::new(StubBackingStore::default()));
+
+        match cache_service.get_cache_value("FAILING") {
+            Err(CacheError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused),
+            v => panic!("Expected an io error, got {:?}", v),
+        }
+
+        let error = CacheError::ValueTooLarge { size: 2, max: 1 };
+        assert_eq!(
+            error.to_string(),
+            "value of 2 bytes exceeds the maximum of 1 bytes"
+        );
+    }
+
+    #[test]
+    fn test_promote_on_read() {
+        let mut cache_service = test_cache_at("promote_on_read");
+        let folder = std::env::temp_dir()
+            .join("rust_fast_cache_promote_on_read")
+            .join("HOT");
+        cache_service.set_instrument(true);
+        cache_service.set_promote_on_read(true);
+        cache_service.insert_cache_item(String::from("HOT"), vec![7; 1024]).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        cache_service.resize_cache(Some(ONE_MEBIBYTE), None, None).unwrap();
+        assert_eq!(cache_service.disk_used(), 1024);
+
+        for _ in 0..2 {
+            assert_eq!(
+                cache_service.get_cache_value("HOT").unwrap(),
+                Some(vec![7; 1024])
+            );
+        }
+
+        let stats = cache_service.stats();
+        assert_eq!(stats.get_disk_timings.count, 1);
+        assert_eq!(stats.get_memory_timings.count, 1);
+        assert!(!folder.exists());
+        assert_eq!(cache_service.disk_used(), 0);
+        assert!(cache_service.memory_used() > 1024);
+    }
+
+    #[test]
+    fn test_insert_into_vec() {
+        let mut cache_service = test_cache_at("insert_into_vec");
+        let slice: &[u8] = &[1, 2, 3];
+        cache_service.insert_cache_item(String::from("SLICE"), slice).unwrap();
+        cache_service.insert_cache_item(String::from("STRING"), String::from("value")).unwrap();
+        cache_service.insert_write_through(String::from("STR"), "value").unwrap();
+
+        assert_eq!(
+            cache_service.get_cache_value("SLICE").unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(
+            cache_service.get_cache_value("STRING").unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(
+            cache_service.get_cache_value("STR").unwrap(),
+            Some(b"value".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_hit_miss_stats() {
+        let mut cache_service = test_cache_at("hit_miss_stats");
+        for i in 0..4 {
+            cache_service.insert_cache_item(format!("STATS_{}", i), vec![0; 1024]).unwrap();
+        }
+        // Room for one of the values and the entries of all four.
+        let max_ram = 1024 + 4 * (ENTRY_OVERHEAD + 128);
+        cache_service.resize_cache(Some(max_ram), None, Some(CleanseStrategy::LastAccess)).unwrap();
+
+        cache_service.get_cache_value("STATS_0").unwrap();
+        cache_service.get_cache_value("STATS_3").unwrap();
+        cache_service.get_cache_value("STATS_3").unwrap();
+        cache_service.get_cache_value("ABSENT").unwrap();
+
+        cache_service.resize_cache(Some(max_ram), Some(1024), Some(CleanseStrategy::LastAccess)).unwrap();
+
+        let stats = cache_service.stats();
+        assert_eq!(stats.insertions, 4);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.mem_hits, 2);
+        assert_eq!(stats.disk_hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 2);
+    }
+
+    #[test]
+    fn test_disk_compression() {
+        let mut cache_service = test_cache_at("disk_compression");
+        let value: Vec<u8> = (0..64 * 1024).map(|v| (v % 16) as u8).collect();
+
+        cache_service.set_disk_compression(Compression::Zstd);
+        cache_service.insert_cache_item(String::from("ZSTD"), value.clone()).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        let zstd_size = cache_service.disk_used();
+        assert!(zstd_size < value.len() as u64);
+
+        cache_service.resize_cache(Some(ONE_GIBIBYTE), None, None).unwrap();
+        cache_service.set_disk_compression(Compression::Lz4);
+        cache_service.insert_cache_item(String::from("LZ4"), value.clone()).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+        let lz4_size = cache_service.disk_used() - zstd_size;
+        assert!(lz4_size > 0 && lz4_size < value.len() as u64);
+
+        cache_service.set_disk_compression(Compression::None);
+        for key in &["ZSTD", "LZ4"] {
+            assert_eq!(cache_service.peek(key).unwrap(), Some(value.clone()));
+        }
+        let report = cache_service.verify_all().unwrap();
+        assert_eq!(report.ok, vec![String::from("LZ4"), String::from("ZSTD")]);
+    }
+
+    #[test]
+    fn test_unaligned_disk_write() {
+        let mut cache_service = test_cache_at("unaligned_disk_write");
+        let value: Vec<u8> = (0..4097).map(|v| (v % 251) as u8).collect();
+        cache_service.insert_cache_item(String::from("UNALIGNED"), value.clone()).unwrap();
+        cache_service.resize_cache(Some(0), None, None).unwrap();
+
+        assert_eq!(cache_service.disk_used(), 4097);
+        assert_eq!(
+            cache_service.get_cache_value("UNALIGNED").unwrap(),
+            Some(value)
+        );
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let mut cache_service = test_cache_at("max_entries");
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_max_entries");
+        cache_service.resize_cache(None, None, Some(CleanseStrategy::LastAccess)).unwrap();
+        for i in 0..4 {
+            cache_service.insert_cache_item(format!("ENTRY_{}", i), vec![0; 64]).unwrap();
+        }
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        cache_service.resize_cache(None, None, Some(CleanseStrategy::LastAccess)).unwrap();
+
+        cache_service.set_max_entries(Some(3)).unwrap();
+        assert_eq!(cache_service.len(), 3);
+        assert!(!cache_service.contains_key("ENTRY_0"));
+        assert!(!cache_path.join("ENTRY_0").exists());
+
+        cache_service.insert_cache_item(String::from("ENTRY_4"), vec![0; 64]).unwrap();
+        assert_eq!(cache_service.len(), 3);
+        assert!(!cache_service.contains_key("ENTRY_1"));
+        assert!(cache_service.contains_key("ENTRY_4"));
+        assert_eq!(cache_service.disk_used(), 2 * 64);
+        assert_eq!(cache_service.stats().evictions, 2);
+    }
+
+    #[test]
+    fn test_lru_order() {
+        let mut memdb = FastDB::default();
+        for (key, last_access) in [("B", 20), ("A", 10), ("C", 30), ("D", 15)] {
+            let mut item = DatabaseItem::new(vec![0; 8]);
+            item.last_access = last_access;
+            memdb.set(String::from(key), item).unwrap();
+        }
+        assert_eq!(
+            memdb.eviction_candidates(&CleanseStrategy::LastAccess, 4),
+            vec!["A", "D", "B", "C"]
+        );
+
+        memdb.get_and_touch("A", 40).unwrap();
+        let mut item = DatabaseItem::new(vec![0; 8]);
+        item.last_access = 5;
+        memdb.set(String::from("C"), item).unwrap();
+        memdb.del("D").unwrap();
+        assert_eq!(
+            memdb.eviction_candidates(&CleanseStrategy::LastAccess, 4),
+            vec!["C", "B", "A"]
+        );
+    }
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let mut cache_service = test_cache_at("get_or_insert_with");
+        let mut calls = 0;
+        for _ in 0..3 {
+            let value = cache_service
+                .get_or_insert_with(String::from("COMPUTED"), || {
+                    calls += 1;
+                    vec![4, 2]
+                })
+                .unwrap();
+            assert_eq!(value, vec![4, 2]);
+        }
+        assert_eq!(calls, 1);
+        assert!(cache_service.contains_key("COMPUTED"));
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut cache_service = test_cache_at("keys");
+        cache_service.insert_cache_item(String::from("KEY_COLD"), vec![0; 16]).unwrap();
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        cache_service.resize_cache(Some(ONE_GIBIBYTE), None, None).unwrap();
+        cache_service.insert_cache_item(String::from("KEY_HOT"), vec![0; 16]).unwrap();
+        cache_service.get_cache_item("KEY_HOT").unwrap();
+
+        let mut keys = cache_service.keys();
+        keys.sort();
+        assert_eq!(keys, vec!["KEY_COLD", "KEY_HOT"]);
+
+        let mut metadata = cache_service.iter_metadata();
+        metadata.sort();
+        assert_eq!(metadata[0].0, "KEY_COLD");
+        assert!(metadata[0].3);
+        assert_eq!(metadata[1].0, "KEY_HOT");
+        assert!(!metadata[1].3);
+        assert!(metadata[1].1 > metadata[0].1);
+    }
+
+    #[test]
+    fn test_item_ttl() {
+        let mut cache_service = test_cache_at("item_ttl");
+        cache_service
+            .insert_cache_item_with_ttl(String::from("SHORT_LIVED"), vec![1], Duration::from_millis(50))
+            .unwrap();
+        cache_service.insert_cache_item(String::from("LONG_LIVED"), vec![2]).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(cache_service.get_cache_value("SHORT_LIVED").unwrap(), None);
+        assert!(!cache_service.contains_key("SHORT_LIVED"));
+        assert_eq!(
+            cache_service.get_cache_value("LONG_LIVED").unwrap(),
+            Some(vec![2])
+        );
+    }
+
+    #[test]
+    fn test_sharded_memdb() {
+        let memdb = FastDB::with_shards(8);
+        assert_eq!(memdb.shard_count(), 8);
+        assert_eq!(FastDB::with_shards(0).shard_count(), 1);
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let mut memdb = memdb.clone();
+                std::thread::spawn(move || {
+                    for i in 0..64 {
+                        let mut item = DatabaseItem::new(vec![t; 8]);
+                        item.last_access = i * 4 + t as u128;
+                        memdb.set(format!("{}_{}", t, i), item).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(memdb.len(), 256);
+        assert_eq!(*memdb.get("3_10").unwrap().unwrap().value.unwrap(), vec![3; 8]);
+        // The access order is kept across shards.
+        assert_eq!(
+            memdb.eviction_candidates(&CleanseStrategy::LastAccess, 6),
+            vec!["0_0", "1_0", "2_0", "3_0", "0_1", "1_1"]
+        );
+    }
+
+    #[test]
+    fn test_corrupt_disk_value() {
+        let mut cache_service = test_cache_at("corrupt_disk_value");
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_corrupt_disk_value");
+        cache_service.insert_cache_item(String::from("TRUNCATED"), vec![7; 4096]).unwrap();
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(cache_path.join("TRUNCATED").join("cachefile"))
+            .unwrap()
+            .set_len(1024)
+            .unwrap();
+
+        match cache_service.get_cache_value("TRUNCATED") {
+            Err(CacheError::Corrupt { key }) => assert_eq!(key, "TRUNCATED"),
+            v => panic!("Expected a corrupt value, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_atomic_disk_write() {
+        let mut cache_service = test_cache_at("atomic_disk_write");
+        let folder = std::env::temp_dir()
+            .join("rust_fast_cache_atomic_disk_write")
+            .join("ATOMIC");
+        cache_service.insert_write_through(String::from("ATOMIC"), vec![1; 1024]).unwrap();
+        cache_service.insert_write_through(String::from("ATOMIC"), vec![2; 512]).unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&folder)
+            .unwrap()
+            .map(|v| v.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["cachefile"]);
+        assert_eq!(std::fs::read(folder.join("cachefile")).unwrap(), vec![2; 512]);
+    }
+
+    #[test]
+    fn test_get_cache_value_ref() {
+        let mut cache_service = test_cache_at("get_cache_value_ref");
+        cache_service.set_max_chunk_size(Some(1000));
+        let value: Vec<u8> = (0..4096).map(|v| v as u8).collect();
+        for (key, compression) in &[
+            ("STREAM_PLAIN", Compression::None),
+            ("STREAM_ZSTD", Compression::Zstd),
+            ("STREAM_LZ4", Compression::Lz4),
+        ] {
+            cache_service.set_disk_compression(*compression);
+            cache_service.insert_cache_item(String::from(*key), value.clone()).unwrap();
+            cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+            cache_service.resize_cache(Some(ONE_GIBIBYTE), None, None).unwrap();
+        }
+        cache_service.insert_cache_item(String::from("STREAM_MEM"), value.clone()).unwrap();
+
+        for key in &["STREAM_PLAIN", "STREAM_ZSTD", "STREAM_LZ4", "STREAM_MEM"] {
+            let mut reader = cache_service
+                .get_cache_value_ref(key)
+                .unwrap()
+                .unwrap();
+            let mut buff = vec![];
+            reader.read_to_end(&mut buff).unwrap();
+            assert_eq!(buff, value);
+        }
+        assert!(cache_service
+            .get_cache_value_ref("STREAM_MISSING")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_log_level() {
+        let _logger = LOGGER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        assert!(!logger::enabled(logger::Severity::DEBUG));
+        assert!(logger::enabled(logger::Severity::LOG));
+
+        logger::set_level(logger::Severity::WARN);
+        assert!(!logger::enabled(logger::Severity::LOG));
+        assert!(logger::enabled(logger::Severity::WARN));
+        assert!(logger::enabled(logger::Severity::ERROR));
+        logger::set_level(logger::Severity::LOG);
+    }
+
+    #[test]
+    fn test_log_sink() {
+        let _logger = LOGGER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let captured = Arc::new(std::sync::Mutex::new(vec![]));
+        let sink_captured = Arc::clone(&captured);
+        logger::set_sink(Box::new(move |severity, msg| {
+            if msg.starts_with("SINK_TEST") {
+                sink_captured.lock().unwrap().push((severity, msg.to_owned()));
+            }
+        }));
+        logger::warn("SINK_TEST warn");
+        logger::debug("SINK_TEST debug");
+        logger::reset_sink();
+        logger::error("SINK_TEST after reset");
+
+        assert_eq!(
+            *captured.lock().unwrap(),
+            vec![(logger::Severity::WARN, String::from("SINK_TEST warn"))]
+        );
+    }
+
+    #[test]
+    fn test_insert_access_counter() {
+        let mut cache_service = test_cache_at("insert_access_counter");
+        cache_service.insert_cache_item(String::from("COUNTED"), vec![1]).unwrap();
+        assert_eq!(cache_service.iter_metadata()[0].2, 0);
+
+        let item = cache_service.get_cache_item("COUNTED").unwrap().unwrap();
+        assert_eq!(item.access_counter, 1);
+    }
+
+    #[test]
+    fn test_disk_used_accounting() {
+        let mut cache_service = test_cache_at("disk_used_accounting");
+        cache_service.insert_cache_item(String::from("SPILLED"), vec![3; 3000]).unwrap();
+        cache_service.insert_cache_item(String::from("REPLACED"), vec![4; 1000]).unwrap();
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+
+        let item = cache_service.get_cache_item("SPILLED").unwrap().unwrap();
+        assert_eq!(cache_service.disk_used(), item.get_disk_size().unwrap() + 1000);
+
+        cache_service.remove_cache_item("SPILLED").unwrap();
+        assert_eq!(cache_service.disk_used(), 1000);
+        cache_service.resize_cache(Some(ONE_GIBIBYTE), None, None).unwrap();
+        cache_service.insert_cache_item(String::from("REPLACED"), vec![5; 10]).unwrap();
+        assert_eq!(cache_service.disk_used(), 0);
+    }
+
+    #[test]
+    fn test_fmt_bytes_si() {
+        assert_eq!(fmt_bytes_si(999), "999 bytes");
+        assert_eq!(fmt_bytes_si(1500), "2 kB");
+        assert_eq!(fmt_bytes_si(5_000_000), "5 MB");
+        assert_eq!(fmt_bytes(5_000_000), "5 MiB");
+    }
+
+    #[test]
+    fn test_builder() {
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_builder");
+        let _ = std::fs::remove_dir_all(&cache_path);
+        let mut cache_service = Cache::builder()
+            .max_ram(ONE_KIBIBYTE)
+            .max_disk(ONE_MEBIBYTE)
+            .decache_age(Duration::from_secs(60))
+            .cache_path(cache_path.clone())
+            .cleanse_strategy(CleanseStrategy::LastAccess)
+            .num_management_threads(0)
+            .build()
+            .unwrap();
+        assert!(cache_path.is_dir());
+        assert_eq!(cache_service.management_threads(), 0);
+
+        cache_service
+            .get_or_insert_with(String::from("BUILT"), || vec![1; 2048])
+            .unwrap();
+        assert!(cache_path.join("BUILT").exists());
+
+        match Cache::builder().max_ram(0).build() {
+            Err(CacheError::InvalidConfig(_)) => {}
+            v => panic!("Expected an invalid config, got {:?}", v.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_set_decache_age() {
+        let mut cache_service = test_cache_at("set_decache_age");
+        cache_service.insert_cache_item(String::from("AGED"), vec![1]).unwrap();
+        cache_service.set_decache_age(Duration::from_secs(0));
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(cache_service.get_cache_value("AGED").unwrap(), None);
+        assert!(!cache_service.contains_key("AGED"));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let mut cache_service = test_cache_at("namespaces");
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_namespaces");
+        cache_service.insert_cache_item_ns("users", "1", vec![1; 100]).unwrap();
+        cache_service.insert_cache_item_ns("users", "2", vec![2; 100]).unwrap();
+        cache_service.insert_cache_item_ns("orders", "1", vec![3; 100]).unwrap();
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        cache_service.resize_cache(Some(ONE_GIBIBYTE), None, None).unwrap();
+        cache_service.insert_cache_item_ns("users", "3", vec![4; 100]).unwrap();
+        assert!(cache_path.join("%ns").join("users").join("1").exists());
+
+        let freed = cache_service.evict_namespace("users").unwrap();
+        assert!(freed >= 100 + 2 * 100);
+        assert!(!cache_path.join("%ns").join("users").exists());
+        assert_eq!(cache_service.get_cache_value_ns("users", "3").unwrap(), None);
+        assert_eq!(
+            cache_service.get_cache_value_ns("orders", "1").unwrap(),
+            Some(vec![3; 100])
+        );
+        assert_eq!(cache_service.disk_used(), 100);
+
+        match cache_service.insert_cache_item_ns("a/b", "1", vec![]) {
+            Err(CacheError::KeyInvalid(v)) => assert_eq!(v, "a/b"),
+            v => panic!("Expected an invalid key, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_insert_many_get_many() {
+        let mut cache_service = test_cache_at("insert_many_get_many");
+        cache_service.insert_cache_item(String::from("BATCH_0"), vec![9; 64]).unwrap();
+        let items: Vec<(String, Vec<u8>)> =
+            (0..100).map(|i| (format!("BATCH_{}", i), vec![i as u8; 64])).collect();
+        cache_service.insert_many(items).unwrap();
+        assert_eq!(cache_service.len(), 100);
+        assert_eq!(cache_service.stats().insertions, 101);
+
+        let keys = vec![
+            String::from("BATCH_5"),
+            String::from("BATCH_MISSING"),
+            String::from("BATCH_5"),
+            String::from("BATCH_99"),
+        ];
+        let values = cache_service.get_many(&keys).unwrap();
+        assert_eq!(values, vec![Some(vec![5; 64]), None, Some(vec![5; 64]), Some(vec![99; 64])]);
+
+        let item = cache_service.get_cache_item("BATCH_5").unwrap().unwrap();
+        assert_eq!(item.access_counter, 3);
+        let stats = cache_service.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 1));
+
+        // The replaced BATCH_0 was subtracted, so removing everything ends at 0.
+        for i in 0..100 {
+            cache_service.remove_cache_item(&format!("BATCH_{}", i)).unwrap();
+        }
+        assert_eq!(cache_service.memory_used(), 0);
+    }
+
+    #[test]
+    fn test_custom_hasher() {
+        let mut memdb = FastDB::with_hasher(4, std::collections::hash_map::RandomState::new());
+        for (key, last_access) in [("SIP_B", 20), ("SIP_A", 10)] {
+            let mut item = DatabaseItem::new(vec![1; 8]);
+            item.last_access = last_access;
+            memdb.set(String::from(key), item).unwrap();
+        }
+
+        assert_eq!(*memdb.get("SIP_A").unwrap().unwrap().value.unwrap(), vec![1; 8]);
+        assert_eq!(
+            memdb.eviction_candidates(&CleanseStrategy::LastAccess, 2),
+            vec!["SIP_A", "SIP_B"]
+        );
+        memdb.del("SIP_A").unwrap();
+        assert_eq!(memdb.keys(), vec!["SIP_B"]);
+    }
+
+    #[test]
+    fn test_cleanup_batches() {
+        let mut cache_service = test_cache_at("cleanup_batches");
+        cache_service.set_cleanup_batch_size(ONE_KIBIBYTE);
+        for i in 0..20 {
+            cache_service.insert_cache_item(format!("BATCHED_{}", i), vec![0; 1024]).unwrap();
+        }
+
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        assert_eq!(cache_service.disk_used(), 20 * 1024);
+        assert!(cache_service.memory_used() < 20 * (ENTRY_OVERHEAD + 100));
+
+        cache_service.resize_cache(
+            Some(0),
+            Some(5 * 1024),
+            Some(CleanseStrategy::LastAccess),
+        ).unwrap();
+        assert_eq!(cache_service.disk_used(), 5 * 1024);
+        assert_eq!(cache_service.len(), 5);
+        assert!(cache_service.contains_key("BATCHED_19"));
+    }
+
+    #[test]
+    fn test_insert_enforces_max_ram() {
+        let mut cache_service = test_cache_at("insert_enforces_max_ram");
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_insert_enforces_max_ram");
+        cache_service.resize_cache(Some(4 * 1024), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        for i in 0..6 {
+            cache_service.insert_cache_item(format!("RAM_{}", i), vec![0; 1000]).unwrap();
+        }
+
+        assert!(cache_service.memory_used() <= 4 * 1024);
+        assert!(cache_path.join("RAM_0").exists());
+        assert!(cache_path.join("RAM_1").exists());
+        assert!(!cache_path.join("RAM_5").exists());
+
+        // Too large for memory on its own, so it doesn't push anything else out.
+        cache_service.insert_cache_item(String::from("RAM_HUGE"), vec![1; 8192]).unwrap();
+        assert!(cache_path.join("RAM_HUGE").exists());
+        assert!(!cache_path.join("RAM_5").exists());
+        assert_eq!(
+            cache_service.get_cache_value("RAM_HUGE").unwrap(),
+            Some(vec![1; 8192])
+        );
+    }
+
+    #[test]
+    fn test_value_larger_than_disk() {
+        let mut cache_service = test_cache_at("value_larger_than_disk");
+        cache_service.resize_cache(Some(ONE_KIBIBYTE), Some(4096), None).unwrap();
+
+        match cache_service.insert_cache_item(String::from("OVERSIZED"), vec![0; 4097]) {
+            Err(CacheError::ValueTooLarge { size, max }) => assert_eq!((size, max), (4097, 4096)),
+            v => panic!("Expected a too large value, got {:?}", v),
+        }
+        assert!(!cache_service.contains_key("OVERSIZED"));
+        cache_service.insert_cache_item(String::from("FITS"), vec![0; 4096]).unwrap();
+
+        let items = vec![
+            (String::from("BATCH_FITS"), vec![0; 16]),
+            (String::from("BATCH_OVERSIZED"), vec![0; 4097]),
+        ];
+        assert!(cache_service.insert_many(items).is_err());
+        assert!(!cache_service.contains_key("BATCH_FITS"));
+    }
+
+    #[test]
+    fn test_get_with_ttl() {
+        let mut cache_service = test_cache_at("get_with_ttl");
+        cache_service.set_decache_age(Duration::from_secs(60));
+        cache_service
+            .insert_cache_item_with_ttl(String::from("TTL_SHORT"), vec![1], Duration::from_millis(500))
+            .unwrap();
+        cache_service.insert_cache_item(String::from("TTL_DEFAULT"), vec![2]).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let (value, remaining) = cache_service
+            .get_with_ttl("TTL_SHORT")
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, vec![1]);
+        assert!(remaining <= Duration::from_millis(400));
+        let (_, remaining) = cache_service
+            .get_with_ttl("TTL_DEFAULT")
+            .unwrap()
+            .unwrap();
+        assert!(remaining > Duration::from_secs(59) && remaining <= Duration::from_secs(60));
+
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(cache_service.get_with_ttl("TTL_SHORT").unwrap(), None);
+        assert!(!cache_service.contains_key("TTL_SHORT"));
+    }
+
+    #[test]
+    fn test_parallel_spill() {
+        let mut cache_service = test_cache_at("parallel_spill");
+        cache_service.set_management_threads(4);
+        for i in 0..64 {
+            cache_service.insert_cache_item(format!("PARALLEL_{}", i), vec![i as u8; 512]).unwrap();
+        }
+
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        assert_eq!(cache_service.disk_used(), 64 * 512);
+        for i in 0..64 {
+            assert_eq!(
+                cache_service.peek(&format!("PARALLEL_{}", i)).unwrap(),
+                Some(vec![i as u8; 512])
+            );
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_database_item_serde() {
+        let item = DatabaseItem {
+            value: Some(Arc::new(vec![1, 2, 3])),
+            last_access: 42,
+            created_at: 7,
+            access_counter: 3,
+            filepath: Some(std::path::PathBuf::from("cache/SERDE")),
+            value_hash: None,
+            boosted_until: None,
+            chunks: 0,
+            compressed: false,
+            ttl: Some(1000),
+        };
+
+        let json = serde_json::to_string(&item).unwrap();
+        let back: DatabaseItem = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.value, item.value);
+        assert_eq!(back.filepath, item.filepath);
+        assert_eq!(back.last_access, 42);
+        assert_eq!(back.access_counter, 3);
+        assert_eq!(back.ttl, Some(1000));
+    }
+
+    #[test]
+    fn test_memdb_update() {
+        let mut db = FastDB::default();
+        db.set(String::from("UPDATE"), DatabaseItem::new(vec![1, 2])).unwrap();
+        db.set(String::from("OTHER"), DatabaseItem::new(vec![3])).unwrap();
+
+        assert!(db.update("UPDATE", |v| {
+            v.access_counter += 5;
+            v.last_access = 0;
+        }));
+        assert!(!db.update("MISSING", |v| v.access_counter += 1));
+
+        let item = db.get("UPDATE").unwrap().unwrap();
+        assert_eq!(item.access_counter, 5);
+        assert_eq!(item.value, Some(Arc::new(vec![1, 2])));
+        assert_eq!(
+            db.eviction_candidates(&CleanseStrategy::LastAccess, 1),
+            vec![String::from("UPDATE")]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_get_insert() {
+        let memdb = FastDB::with_shards(4);
+        let mut writer_db = memdb.clone();
+        let writer = std::thread::spawn(move || {
+            for i in 0..2000u32 {
+                writer_db
+                    .set(String::from("CONTENDED"), DatabaseItem::new(i.to_le_bytes().to_vec()))
+                    .unwrap();
+            }
+        });
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let memdb = memdb.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..2000 {
+                        memdb.update("CONTENDED", |v| {
+                            v.last_access = get_nano_time();
+                            v.access_counter += 1;
+                        });
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        // Reads never write back a stale copy over the last insert.
+        let item = memdb.get("CONTENDED").unwrap().unwrap();
+        assert_eq!(*item.value.unwrap(), 1999u32.to_le_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_flush_to_disk() {
+        let mut cache_service = test_cache_at("flush_to_disk");
+        for i in 0..4 {
+            cache_service.insert_cache_item(format!("FLUSH_{}", i), vec![i as u8; 256]).unwrap();
+        }
+
+        assert_eq!(cache_service.flush_to_disk().unwrap(), 4 * 256);
+        assert_eq!(cache_service.disk_used(), 4 * 256);
+        assert!(cache_service.memory_used() < 4 * (ENTRY_OVERHEAD + 256));
+        assert_eq!(cache_service.flush_to_disk().unwrap(), 0);
+        assert_eq!(cache_service.disk_used(), 4 * 256);
+
+        for i in 0..4 {
+            assert_eq!(
+                cache_service.peek(&format!("FLUSH_{}", i)).unwrap(),
+                Some(vec![i as u8; 256])
+            );
+        }
+    }
+
+    #[test]
+    fn test_rebuild_from_disk() {
+        let mut cache_service = test_cache_at("rebuild_from_disk");
+        cache_service.insert_cache_item(String::from("REBUILT"), vec![1; 128]).unwrap();
+        cache_service.insert_cache_item_ns("ns", "NESTED", vec![2; 64]).unwrap();
+        cache_service.flush_to_disk().unwrap();
+
+        // A fresh cache on the same path, without the index of the old one.
+        let mut rebuilt = Cache::default();
+        let path = std::env::temp_dir().join("rust_fast_cache_rebuild_from_disk");
+        rebuilt.set_cache_path(String::from(path.to_str().unwrap()), false).unwrap();
+        assert_eq!(rebuilt.rebuild_from_disk().unwrap(), 2);
+        assert_eq!(rebuilt.rebuild_from_disk().unwrap(), 0);
+        assert_eq!(rebuilt.disk_used(), 128 + 64);
+        assert_eq!(rebuilt.peek("REBUILT").unwrap(), Some(vec![1; 128]));
+        assert_eq!(rebuilt.get_cache_value_ns("ns", "NESTED").unwrap(), Some(vec![2; 64]));
+    }
+
+    #[test]
+    fn test_get_range() {
+        let mut cache_service = test_cache_at("get_range");
+        cache_service.set_max_chunk_size(Some(100));
+        let value: Vec<u8> = (0..=255).collect();
+        cache_service.insert_cache_item(String::from("RANGE_DISK"), value.clone()).unwrap();
+        cache_service.flush_to_disk().unwrap();
+        cache_service.set_disk_compression(Compression::Zstd);
+        cache_service.insert_cache_item(String::from("RANGE_ZSTD"), value.clone()).unwrap();
+        cache_service.flush_to_disk().unwrap();
+        cache_service.insert_cache_item(String::from("RANGE_MEM"), value.clone()).unwrap();
+
+        for key in &["RANGE_MEM", "RANGE_DISK", "RANGE_ZSTD"] {
+            let range = cache_service.get_range(key, 90, 20).unwrap();
+            assert_eq!(range, Some(value[90..110].to_vec()));
+            let range = cache_service.get_range(key, 250, 20).unwrap();
+            assert_eq!(range, Some(value[250..].to_vec()));
+            assert_eq!(cache_service.get_range(key, 300, 20).unwrap(), Some(vec![]));
+        }
+        assert_eq!(cache_service.get_range("RANGE_MISSING", 0, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_touch() {
+        let mut cache_service = test_cache_at("touch");
+        cache_service.set_decache_age(Duration::from_secs(1));
+        cache_service.insert_cache_item(String::from("KEEP_ALIVE"), vec![1]).unwrap();
+        cache_service.insert_cache_item(String::from("IDLE"), vec![2]).unwrap();
+
+        std::thread::sleep(Duration::from_millis(700));
+        assert!(cache_service.touch("KEEP_ALIVE"));
+        assert!(!cache_service.touch("MISSING"));
+        std::thread::sleep(Duration::from_millis(700));
+
+        assert!(cache_service.touch("KEEP_ALIVE"));
+        assert!(!cache_service.touch("IDLE"));
+        assert!(!cache_service.contains_key("IDLE"));
+        let item = cache_service.get_cache_item("KEEP_ALIVE").unwrap().unwrap();
+        assert_eq!(item.access_counter, 1);
+    }
+
+    #[test]
+    fn test_read_non_buffered() {
+        let dir = std::env::temp_dir().join("rust_fast_cache_read_non_buffered");
+        std::fs::create_dir_all(&dir).unwrap();
+        for len in &[0usize, 1, 4096, 5000] {
+            let path = dir.join(format!("file_{}", len));
+            let data: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+            std::fs::write(&path, &data).unwrap();
+            assert_eq!(read_non_buffered(&path).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_fifo_strategy() {
+        let mut db = FastDB::default();
+        for (i, key) in ["FIRST", "SECOND", "THIRD"].iter().enumerate() {
+            let mut item = DatabaseItem::new(vec![i as u8]);
+            item.created_at = i as u128;
+            item.last_access = 10 - i as u128;
+            item.access_counter = 10 - i as u64;
+            db.set(key.to_string(), item).unwrap();
+        }
+
+        assert_eq!(
+            db.eviction_candidates(&CleanseStrategy::Fifo, 3),
+            vec!["FIRST", "SECOND", "THIRD"]
+        );
+        assert_eq!(
+            db.eviction_candidates(&CleanseStrategy::LastAccess, 1),
+            vec!["THIRD"]
+        );
+    }
+
+    #[test]
+    fn test_size_weighted_strategy() {
+        let mut cache_service = test_cache_at("size_weighted_strategy");
+        cache_service.insert_cache_item(String::from("SMALL"), vec![1; 1000]).unwrap();
+        cache_service.insert_cache_item(String::from("BIG"), vec![2; 5000]).unwrap();
+        cache_service.insert_cache_item(String::from("MEDIUM"), vec![3; 3000]).unwrap();
+
+        // Freeing a few bytes only spills the biggest item.
+        let used = cache_service.memory_used();
+        cache_service.resize_cache(Some(used - 100), None, Some(CleanseStrategy::SizeWeighted)).unwrap();
+        assert_eq!(cache_service.disk_used(), 5000);
+    }
+
+    #[test]
+    fn test_cache_reader() {
+        let mut cache_service = test_cache_at("cache_reader");
+        cache_service.insert_cache_item(String::from("SHARED"), vec![1; 64]).unwrap();
+        cache_service.insert_cache_item(String::from("ON_DISK"), vec![2; 64]).unwrap();
+        cache_service.flush_cold(Duration::from_secs(0)).unwrap();
+        cache_service.insert_cache_item(String::from("SHARED"), vec![1; 64]).unwrap();
+
+        let reader = cache_service.reader();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = reader.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        assert_eq!(reader.get_cache_value("SHARED").unwrap(), Some(vec![1; 64]));
+                        assert_eq!(reader.get_cache_value("ON_DISK").unwrap(), Some(vec![2; 64]));
+                        assert_eq!(reader.get_cache_value("MISSING").unwrap(), None);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(reader.len(), 2);
+        assert!(reader.contains_key("ON_DISK"));
+        let item = cache_service.get_cache_item("SHARED").unwrap().unwrap();
+        assert_eq!(item.access_counter, 401);
+        let stats = cache_service.stats();
+        assert_eq!(stats.hits, 800);
+        assert_eq!(stats.misses, 400);
+    }
+
+    #[test]
+    fn test_concurrent_size_accounting() {
+        let cache_service = test_cache_at("concurrent_size_accounting");
+        let reader = cache_service.reader();
+        let cache_service = Arc::new(std::sync::Mutex::new(cache_service));
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let cache_service = Arc::clone(&cache_service);
+                let reader = reader.clone();
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        let key = format!("SIZE_{}_{}", t, i);
+                        let value = vec![t as u8; i * 10];
+                        cache_service.lock().unwrap().insert_cache_item(key.clone(), value).unwrap();
+                        assert!(reader.get_cache_value(&key).unwrap().is_some());
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let cache_service = cache_service.lock().unwrap();
+        let expected: u64 = cache_service
+            .keys()
+            .into_iter()
+            .map(|k| {
+                let item = cache_service.get_cache_item(&k).unwrap().unwrap();
+                item.get_mem_size() + entry_overhead(&k)
+            })
+            .sum();
+        assert_eq!(cache_service.len(), 200);
+        assert_eq!(cache_service.memory_used(), expected);
+    }
+
+    #[test]
+    fn test_memdb_batches() {
+        let mut db = FastDB::with_shards(4);
+        db.set(String::from("BATCH_0"), DatabaseItem::new(vec![9])).unwrap();
+        let replaced = db.set_many(
+            (0..8)
+                .map(|i| (format!("BATCH_{}", i), DatabaseItem::new(vec![i as u8])))
+                .collect(),
+        );
+        assert_eq!(replaced.iter().filter(|v| v.is_some()).count(), 1);
+        assert_eq!(replaced[0].as_ref().unwrap().value, Some(Arc::new(vec![9])));
+
+        let keys: Vec<String> = vec![String::from("BATCH_7"), String::from("MISSING")];
+        let items = db.get_many(&keys);
+        assert_eq!(items[0].as_ref().unwrap().value, Some(Arc::new(vec![7])));
+        assert!(items[1].is_none());
+    }
+
+    #[test]
+    fn test_max_item_size() {
+        let mut cache_service = test_cache_at("max_item_size");
+        cache_service.set_max_item_size(1024);
+        cache_service.insert_cache_item(String::from("FITS"), vec![1; 1024]).unwrap();
+        match cache_service.insert_cache_item(String::from("HUGE"), vec![2; 1025]) {
+            Err(CacheError::ValueTooLarge { size, max }) => {
+                assert_eq!(size, 1025);
+                assert_eq!(max, 1024);
+            }
+            v => panic!("Expected ValueTooLarge, got {:?}", v),
+        }
+        assert!(cache_service.contains_key("FITS"));
+        assert!(!cache_service.contains_key("HUGE"));
+    }
+
+    #[test]
+    fn test_created_at_metadata() {
+        let mut cache_service = test_cache_at("created_at_metadata");
+        cache_service.insert_cache_item(String::from("AGED"), vec![1]).unwrap();
+        let created_at = cache_service.iter_metadata()[0].4;
+
+        let item = cache_service.get_cache_item("AGED").unwrap().unwrap();
+        assert_eq!(item.created_at, created_at);
+        assert!(format!("{}", item).contains("created_at"));
+        let (_, metadata) = cache_service.get_full("AGED").unwrap().unwrap();
+        assert_eq!(metadata.created_at, created_at);
+    }
+
+    #[test]
+    fn test_memory_report() {
+        let mut cache_service = test_cache_at("memory_report");
+        cache_service.resize_cache(Some(ONE_MEBIBYTE), Some(ONE_MEBIBYTE), None).unwrap();
+        cache_service.insert_cache_item(String::from("REPORT_DISK"), vec![1; 1024]).unwrap();
+        cache_service.flush_to_disk().unwrap();
+        cache_service.insert_cache_item(String::from("REPORT_MEM"), vec![2; 1024]).unwrap();
+
+        let report = cache_service.memory_report();
+        assert_eq!(report.item_count, 2);
+        assert_eq!(report.disk_item_count, 1);
+        assert_eq!(report.mem_bytes, cache_service.memory_used());
+        assert_eq!(report.disk_bytes, 1024);
+        assert!((report.disk_utilization - 1024.0 / ONE_MEBIBYTE as f64).abs() < 1e-9);
+        assert!(report.mem_utilization > 0.0 && report.mem_utilization < 0.01);
+    }
+
+    #[test]
+    fn test_set_cache_path_migrate() {
+        let mut cache_service = test_cache_at("migrate_from");
+        let new_path = std::env::temp_dir().join("rust_fast_cache_migrate_to");
+        let _ = std::fs::remove_dir_all(&new_path);
+        cache_service.insert_cache_item(String::from("MOVED"), vec![1; 64]).unwrap();
+        cache_service.insert_cache_item_ns("ns", "MOVED", vec![2; 64]).unwrap();
+        cache_service.flush_to_disk().unwrap();
+
+        cache_service.set_cache_path(String::from(new_path.to_str().unwrap()), true).unwrap();
+        assert!(new_path.join("MOVED").join("cachefile").is_file());
+        assert!(!std::env::temp_dir().join("rust_fast_cache_migrate_from/MOVED").exists());
+        assert_eq!(cache_service.peek("MOVED").unwrap(), Some(vec![1; 64]));
+        assert_eq!(cache_service.get_cache_value_ns("ns", "MOVED").unwrap(), Some(vec![2; 64]));
+        assert_eq!(cache_service.disk_used(), 128);
+    }
+
+    #[test]
+    fn test_nano_time_fmt() {
+        assert_eq!(nano_time_fmt(0), "1970-01-01 00:00:00.000000000");
+        // Past u64::MAX nanoseconds, in the year 2554.
+        assert_eq!(
+            nano_time_fmt(u128::from(u64::MAX) + 1),
+            "2554-07-21 23:34:33.709551616"
+        );
+        assert_eq!(nano_time_fmt(u128::MAX), format!("{}ns", u128::MAX));
+    }
+
+    #[test]
+    fn test_log_trace_keyed() {
+        let _logger = LOGGER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        assert!(!logger::enabled(logger::Severity::TRACE));
+
+        let captured = Arc::new(std::sync::Mutex::new(vec![]));
+        let sink_captured = Arc::clone(&captured);
+        logger::set_sink(Box::new(move |severity, msg| {
+            if msg.contains("KEYED_TEST") {
+                sink_captured.lock().unwrap().push((severity, msg.to_owned()));
+            }
+        }));
+        logger::set_level(logger::Severity::TRACE);
+        logger::set_key_filter(Some(String::from("WANTED")));
+        logger::trace("KEYED_TEST trace");
+        logger::keyed(logger::Severity::TRACE, "WANTED", "KEYED_TEST wanted");
+        logger::keyed(logger::Severity::WARN, "OTHER", "KEYED_TEST other");
+        logger::set_key_filter(None);
+        logger::set_level(logger::Severity::LOG);
+        logger::trace("KEYED_TEST dropped");
+        logger::reset_sink();
+
+        assert_eq!(
+            *captured.lock().unwrap(),
+            vec![
+                (logger::Severity::TRACE, String::from("KEYED_TEST trace")),
+                (logger::Severity::TRACE, String::from("\"WANTED\": KEYED_TEST wanted")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_value_speed() {
+        let mut cache_service = test_cache_at("get_value_speed");
+        let value = vec![7; 4 * ONE_MEBIBYTE as usize];
+        cache_service.insert_cache_item(String::from("LARGE"), value).unwrap();
+        let rounds = 64;
+
+        // Clones the whole item and then its value, like gets did before.
+        let now = Instant::now();
+        for _ in 0..rounds {
+            let item = cache_service.get_cache_item("LARGE").unwrap().unwrap();
+            assert_eq!(item.value.unwrap().len(), 4 * ONE_MEBIBYTE as usize);
+        }
+        let elapsed_item = now.elapsed();
+
+        let now = Instant::now();
+        for _ in 0..rounds {
+            let value = cache_service.get_cache_value_shared("LARGE").unwrap();
+            assert_eq!(value.unwrap().len(), 4 * ONE_MEBIBYTE as usize);
+        }
+        let elapsed_value = now.elapsed();
+
+        logger::log(&format!(
+            "get_cache_item: {:?}/get, get_cache_value_shared: {:?}/get",
+            elapsed_item / rounds,
+            elapsed_value / rounds
+        ));
+        let item = cache_service.get_cache_item("LARGE").unwrap().unwrap();
+        assert_eq!(item.access_counter, 2 * u64::from(rounds) + 1);
+    }
+
+    #[test]
+    fn test_write_to_backing_store() {
+        let mut cache_service = test_cache_at("write_to_backing_store");
+        let backing_store = Arc::new(StubBackingStore::default());
+        cache_service.set_backing_store(backing_store.clone());
+
+        cache_service.insert_cache_item(String::from("UNSTORED"), vec![1; 16]).unwrap();
+        assert!(backing_store.stored.lock().unwrap().is_empty());
+
+        cache_service.set_write_to_backing_store(true);
+        cache_service.insert_cache_item(String::from("STORED"), vec![2; 16]).unwrap();
+        cache_service
+            .insert_many(vec![(String::from("STORED_MANY"), vec![3; 16])])
+            .unwrap();
+        assert_eq!(
+            cache_service.get_cache_value("BACKED_1").unwrap(),
+            Some(b"BACKED_1".to_vec())
+        );
+
+        let stored = backing_store.stored.lock().unwrap();
+        assert_eq!(
+            *stored,
+            vec![
+                (String::from("STORED"), vec![2; 16]),
+                (String::from("STORED_MANY"), vec![3; 16]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stats_reporter() {
+        let mut cache_service = test_cache_at("stats_reporter");
+        cache_service.insert_cache_item(String::from("REPORTED"), vec![0; 2048]).unwrap();
+        cache_service.get_cache_value("REPORTED").unwrap();
+        cache_service.get_cache_value("MISSING").unwrap();
+
+        let summary = cache_service.stats_summary();
+        assert!(summary.contains("1 items (0 on disk)"), "{}", summary);
+        assert!(summary.contains(&fmt_bytes(cache_service.memory_used())), "{}", summary);
+        assert!(summary.contains("hit ratio 50.0% of 2 gets"), "{}", summary);
+
+        cache_service.start_stats_reporter(Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(20));
+        cache_service.stop_stats_reporter();
+        cache_service.stop_stats_reporter();
+        cache_service.start_stats_reporter(Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_try_default() {
+        let cache_service = Cache::try_default().unwrap();
+        assert!(cache_service.is_empty());
+        assert_eq!(cache_service.memory_used(), 0);
+    }
+
+    #[test]
+    fn test_insert_returns_previous() {
+        let mut cache_service = test_cache_at("insert_returns_previous");
+        assert!(cache_service
+            .insert_cache_item(String::from("PREVIOUS"), vec![1; 64])
+            .unwrap()
+            .is_none());
+
+        let previous = cache_service
+            .insert_cache_item(String::from("PREVIOUS"), vec![2; 64])
+            .unwrap()
+            .unwrap();
+        assert_eq!(previous.value.as_deref(), Some(&vec![1; 64]));
+
+        cache_service.flush_to_disk().unwrap();
+        let previous = cache_service
+            .insert_cache_item(String::from("PREVIOUS"), vec![3; 64])
+            .unwrap()
+            .unwrap();
+        // Overwriting doesn't read the previous value back from disk.
+        assert!(previous.value.is_none());
+        assert_eq!(cache_service.disk_used(), 0);
+        assert_eq!(
+            cache_service.get_cache_value("PREVIOUS").unwrap(),
+            Some(vec![3; 64])
+        );
+    }
+
+    #[test]
+    fn test_sharded_disk_layout() {
+        let mut cache_service = test_cache_at("sharded_disk_layout");
+        let path = std::env::temp_dir().join("rust_fast_cache_sharded_disk_layout");
+        let path_str = path.to_str().unwrap();
+        cache_service.set_disk_layout(DiskLayout::Sharded);
+        cache_service.insert_cache_item(String::from("SHARDED"), vec![1; 128]).unwrap();
+        cache_service.insert_cache_item_ns("ns", "NESTED", vec![2; 64]).unwrap();
+        cache_service.flush_to_disk().unwrap();
+
+        let folder = Path::new(&DiskLayout::Sharded.folder(path_str, "SHARDED")).to_path_buf();
+        assert!(folder.join("cachefile").exists());
+        assert!(!path.join("SHARDED").exists());
+        assert_eq!(
+            cache_service.get_cache_value("SHARDED").unwrap(),
+            Some(vec![1; 128])
+        );
+
+        // A fresh cache on the same path, without the index of the old one.
+        let mut rebuilt = Cache::default();
+        rebuilt.set_cache_path(String::from(path_str), false).unwrap();
+        rebuilt.set_disk_layout(DiskLayout::Sharded);
+        assert_eq!(rebuilt.rebuild_from_disk().unwrap(), 2);
+        assert_eq!(rebuilt.peek("SHARDED").unwrap(), Some(vec![1; 128]));
+
+        let nested = DiskLayout::Sharded.folder(path_str, "ns/NESTED");
+        let shard = Path::new(&nested).ancestors().nth(2).unwrap().to_path_buf();
+        assert!(shard.starts_with(&path) && shard != path);
+        assert!(shard.exists());
+        cache_service.evict_namespace("ns").unwrap();
+        assert!(!Path::new(&nested).exists());
+        // The shard folders the namespace left empty are removed as well.
+        assert!(!shard.exists());
+        cache_service.resize_cache(None, Some(0), None).unwrap();
+        assert!(!folder.exists());
+    }
+
+    #[test]
+    fn test_key_path_sanitization() {
+        for key in &["../evil", "/abs", "a//b", ".", "..", "ns/KEY", "100%", "ünï côdé", ""] {
+            let encoded = encode_key_path(key);
+            assert!(!encoded.is_empty() && encoded != "." && encoded != "..");
+            assert!(!encoded.contains('/'));
+            assert_eq!(decode_key_path(&encoded).as_deref(), Some(*key));
+        }
+        assert_eq!(encode_key_path("PLAIN_key-1.bin"), "PLAIN_key-1.bin");
+        assert_eq!(decode_key_path("%G0"), None);
+        assert_eq!(decode_key_path("a/b"), None);
+
+        let mut cache_service = test_cache_at("key_path_sanitization");
+        let path = std::env::temp_dir().join("rust_fast_cache_key_path_sanitization");
+        let outside = std::env::temp_dir().join("evil");
+        let _ = std::fs::remove_dir_all(&outside);
+        cache_service.insert_cache_item(String::from("../evil"), vec![1; 64]).unwrap();
+        cache_service.insert_cache_item_ns("..", "escaped", vec![2; 64]).unwrap();
+        cache_service.flush_to_disk().unwrap();
+
+        assert!(!outside.exists());
+        assert!(path.join("%ns").join("%2E%2E").join("evil").join("cachefile").exists());
+        assert_eq!(
+            cache_service.get_cache_value("../evil").unwrap(),
+            Some(vec![1; 64])
+        );
+
+        let mut rebuilt = Cache::default();
+        rebuilt.set_cache_path(String::from(path.to_str().unwrap()), false).unwrap();
+        assert_eq!(rebuilt.rebuild_from_disk().unwrap(), 2);
+        assert_eq!(rebuilt.peek("../evil").unwrap(), Some(vec![1; 64]));
+
+        // "../evil" is in the namespace "..", which only removes its folder inside the cache.
+        cache_service.evict_namespace("..").unwrap();
+        assert!(cache_service.is_empty());
+        assert!(path.exists());
+        assert!(!path.join("%ns").exists());
+
+        // Every key has its own folder, removing "a" doesn't touch the file of "a/b".
+        cache_service.insert_cache_item(String::from("a"), vec![3; 64]).unwrap();
+        cache_service.insert_cache_item(String::from("a/b"), vec![4; 64]).unwrap();
+        cache_service.flush_to_disk().unwrap();
+        cache_service.remove_cache_item("a").unwrap();
+        assert_eq!(cache_service.get_cache_value("a/b").unwrap(), Some(vec![4; 64]));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_cache() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let cache_service = test_cache_at("async_cache").into_async();
+
+        runtime.block_on(async {
+            cache_service
+                .insert_cache_item_async(String::from("ASYNC_MEM"), vec![1; 64])
+                .await
+                .unwrap();
+            cache_service
+                .insert_cache_item_async(String::from("ASYNC_DISK"), vec![2; 64])
+                .await
+                .unwrap();
+            cache_service.lock().flush_to_disk().unwrap();
+            cache_service
+                .insert_cache_item_async(String::from("ASYNC_MEM"), vec![3; 64])
+                .await
+                .unwrap();
+
+            let value = cache_service.get_cache_value_async("ASYNC_MEM").await.unwrap();
+            assert_eq!(value, Some(vec![3; 64]));
+            let value = cache_service.get_cache_value_async("ASYNC_DISK").await.unwrap();
+            assert_eq!(value, Some(vec![2; 64]));
+            assert!(cache_service.get_cache_value_async("MISSING").await.unwrap().is_none());
+        });
+
+        let stats = cache_service.lock().stats();
+        assert_eq!((stats.mem_hits, stats.disk_hits, stats.misses), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_entry_overhead() {
+        let mut cache_service = test_cache_at("entry_overhead");
+        let key = |i: usize| format!("ENTRY_OVERHEAD_WITH_A_LONGER_KEY_{:08}", i);
+        for i in 0..1000 {
+            cache_service.insert_cache_item(key(i), vec![0; 16]).unwrap();
+        }
+
+        let expected: u64 = (0..1000)
+            .map(|i| {
+                let item = cache_service.get_cache_item(&key(i)).unwrap().unwrap();
+                item.get_mem_size() + 3 * key(i).len() as u64 + ENTRY_OVERHEAD
+            })
+            .sum();
+        assert_eq!(cache_service.memory_used(), expected);
+
+        // The bucket holds the item inline, only the value is allocated besides it:
+        // the bytes and the `Arc` with its two counters and the `Vec`.
+        let bucket = (std::mem::size_of::<(String, DatabaseItem)>() + 1) as u64;
+        let value = (2 * std::mem::size_of::<usize>() + std::mem::size_of::<Vec<u8>>() + 16) as u64;
+        assert!(ENTRY_OVERHEAD > bucket);
+        assert_eq!(DatabaseItem::new(vec![0; 16]).get_mem_size(), value);
+        assert_eq!(DatabaseItem::default().get_mem_size(), 0);
+        let footprint = 1000 * (ENTRY_OVERHEAD + 3 * key(0).len() as u64 + value);
+        assert_eq!(cache_service.memory_used(), footprint);
+
+        for i in 0..1000 {
+            cache_service.remove_cache_item(&key(i)).unwrap();
+        }
+        assert_eq!(cache_service.memory_used(), 0);
+    }
+
+    #[test]
+    fn test_insert_to_disk() {
+        let mut cache_service = test_cache_at("insert_to_disk");
+        let path = std::env::temp_dir().join("rust_fast_cache_insert_to_disk");
+        cache_service.insert_cache_item(String::from("COLD"), vec![1; 16]).unwrap();
+        let memory_used = cache_service.memory_used();
+
+        let previous = cache_service
+            .insert_cache_item_to_disk(String::from("COLD"), vec![2; 4096])
+            .unwrap();
+        assert_eq!(previous.unwrap().value.as_deref(), Some(&vec![1; 16]));
+        assert!(path.join("COLD").join("cachefile").exists());
+        assert_eq!(cache_service.disk_used(), 4096);
+        assert!(cache_service.memory_used() < memory_used);
+
+        let item = cache_service.get_cache_item("COLD").unwrap().unwrap();
+        assert!(item.value.is_none());
+        assert_eq!(cache_service.get_cache_value("COLD").unwrap(), Some(vec![2; 4096]));
+        assert_eq!(cache_service.stats().disk_hits, 1);
+    }
+
+    #[test]
+    fn test_combined_tiebreak() {
+        // Enough keys that the map order differs from the key order.
+        let keys: Vec<String> = (0..64).rev().map(|i| format!("TIE_{:02}", i)).collect();
+        let mut db = FastDB::default();
+        for key in &keys {
+            let mut item = DatabaseItem::new(vec![0; 8]);
+            item.last_access = 10;
+            db.set(key.to_owned(), item).unwrap();
+        }
+        let mut first = db.get("TIE_40").unwrap().unwrap();
+        first.access_counter = 1;
+        db.set(String::from("TIE_40"), first).unwrap();
+
+        let mut expected: Vec<String> = keys.into_iter().filter(|k| k != "TIE_40").collect();
+        expected.sort();
+        expected.push(String::from("TIE_40"));
+        assert_eq!(db.eviction_candidates(&CleanseStrategy::Combined, 64), expected);
+    }
+
+    #[test]
+    fn test_evict_lru() {
+        let mut cache_service = test_cache_at("evict_lru");
+        for i in 0..4 {
+            cache_service.insert_cache_item(format!("LRU_{}", i), vec![0; 1024]).unwrap();
+        }
+        cache_service.flush_to_disk().unwrap();
+        cache_service.get_cache_value("LRU_0").unwrap();
+        let memory_used = cache_service.memory_used();
+
+        let freed = cache_service.evict_lru(2).unwrap();
+        assert!(freed > 2 * 1024);
+        assert_eq!(cache_service.memory_used() + freed - 2 * 1024, memory_used);
+        assert_eq!(cache_service.disk_used(), 2 * 1024);
+        assert_eq!(cache_service.keys().len(), 2);
+        assert!(cache_service.contains_key("LRU_0"));
+        assert!(cache_service.contains_key("LRU_3"));
+        assert_eq!(cache_service.stats().evictions, 2);
+
+        cache_service.evict_lru(10).unwrap();
+        assert!(cache_service.is_empty());
+        assert_eq!(cache_service.memory_used(), 0);
+        assert_eq!(cache_service.disk_used(), 0);
+    }
+
+    struct U32Codec;
+
+    impl Codec<u32> for U32Codec {
+        fn encode(&self, value: &u32) -> io::Result<Vec<u8>> {
+            Ok(value.to_le_bytes().to_vec())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> io::Result<u32> {
+            if bytes.len() != 4 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a u32"));
+            }
+            let mut le_bytes = [0; 4];
+            le_bytes.copy_from_slice(bytes);
+            Ok(u32::from_le_bytes(le_bytes))
+        }
+    }
+
+    #[test]
+    fn test_typed_cache() {
+        let mut typed = TypedCache::new(test_cache_at("typed_cache"), U32Codec);
+        typed.insert(String::from("TYPED"), &42).unwrap();
+        assert_eq!(typed.get("TYPED").unwrap(), Some(42));
+        assert_eq!(typed.get("MISSING").unwrap(), None);
+
+        typed.cache().flush_to_disk().unwrap();
+        assert_eq!(typed.get("TYPED").unwrap(), Some(42));
+
+        typed.cache().insert_cache_item(String::from("UNTYPED"), vec![1; 3]).unwrap();
+        match typed.get("UNTYPED") {
+            Err(CacheError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            v => panic!("Expected an io error, got {:?}", v),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_typed_cache_json() {
+        use rust_fast_cache::cache_service::typed_cache::JsonCodec;
+
+        let mut typed: TypedCache<Vec<String>, JsonCodec> =
+            TypedCache::json(test_cache_at("typed_cache_json"));
+        let value = vec![String::from("a"), String::from("b")];
+        typed.insert(String::from("JSON"), &value).unwrap();
+        assert_eq!(typed.get("JSON").unwrap(), Some(value));
+        assert_eq!(
+            typed.into_inner().get_cache_value("JSON").unwrap(),
+            Some(br#"["a","b"]"#.to_vec())
+        );
+    }
+
+    #[test]
+    fn test_builder_backing_store() {
+        let backing_store = Arc::new(StubBackingStore::default());
+        let mut cache_service = Cache::builder()
+            .cache_path(std::env::temp_dir().join("rust_fast_cache_builder_backing_store"))
+            .backing_store(backing_store.clone())
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            cache_service.get_cache_value("BACKED_BUILT").unwrap(),
+            Some(b"BACKED_BUILT".to_vec())
+        );
+        assert!(cache_service.contains_key("BACKED_BUILT"));
+        assert_eq!(backing_store.fetches.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_write_to_backing_store_rejected() {
+        let mut cache_service = test_cache_at("write_to_backing_store_rejected");
+        let backing_store = Arc::new(StubBackingStore::default());
+        cache_service.set_backing_store(backing_store.clone());
+        cache_service.set_write_to_backing_store(true);
+        cache_service.set_max_item_size(8);
+
+        for result in [
+            cache_service.insert_cache_item(String::from("TOO_LARGE"), vec![1; 16]),
+            cache_service.insert_cache_item_to_disk(String::from("TOO_LARGE"), vec![1; 16]),
+        ] {
+            match result {
+                Err(CacheError::ValueTooLarge { size: 16, max: 8 }) => {}
+                v => panic!("Expected a value too large, got {:?}", v),
+            }
+        }
+        assert!(cache_service
+            .insert_many(vec![(String::from("TOO_LARGE"), vec![1; 16])])
+            .is_err());
+        assert!(backing_store.stored.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resize_cache_error() {
+        let mut cache_service = test_cache_at("resize_cache_error");
+        cache_service.insert_cache_item(String::from("UNWRITABLE"), vec![1; 64]).unwrap();
+
+        // A file where the cache folder should be, so the items can't be moved to disk.
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_resize_cache_error");
+        std::fs::remove_dir_all(&cache_path).unwrap();
+        std::fs::write(&cache_path, b"not a folder").unwrap();
+
+        match cache_service.resize_cache(Some(0), None, None) {
+            Err(CacheError::Io(_)) => {}
+            v => panic!("Expected an io error, got {:?}", v),
+        }
+        assert_eq!(
+            cache_service.get_cache_value("UNWRITABLE").unwrap(),
+            Some(vec![1; 64])
+        );
+        std::fs::remove_file(&cache_path).unwrap();
+    }
+
+    #[test]
+    fn test_promote_on_disk_evict_compressed() {
+        let mut cache_service = test_cache_at("promote_on_disk_evict_compressed");
+        cache_service.set_promote_on_disk_evict(true);
+        cache_service.set_disk_compression(Compression::Zstd);
+        for key in &["COMPRESSED_COLD", "COMPRESSED_HOT"] {
+            cache_service.insert_cache_item(String::from(*key), vec![0; 64 * 1024]).unwrap();
+        }
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        assert!(cache_service.disk_used() < 1024);
+
+        // Room for the compressed files, but not for a decompressed value.
+        let max_ram = cache_service.memory_used() + 32 * 1024;
+        cache_service
+            .resize_cache(Some(max_ram), Some(0), Some(CleanseStrategy::LastAccess))
+            .unwrap();
+        assert!(cache_service.memory_used() <= max_ram);
+        assert!(cache_service.is_empty());
+    }
+
+    #[test]
+    fn test_promote_on_disk_evict_corrupt() {
+        let mut cache_service = test_cache_at("promote_on_disk_evict_corrupt");
+        cache_service.set_promote_on_disk_evict(true);
+        for key in &["CORRUPT_COLD", "CORRUPT_HOT"] {
+            cache_service.insert_cache_item(String::from(*key), vec![1; 1024]).unwrap();
+        }
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_promote_on_disk_evict_corrupt");
+        std::fs::write(cache_path.join("CORRUPT_HOT").join("cachefile"), vec![2; 1024]).unwrap();
+
+        cache_service
+            .resize_cache(Some(ONE_MEBIBYTE), Some(1024), Some(CleanseStrategy::LastAccess))
+            .unwrap();
+        let cold = cache_service.get_cache_item("CORRUPT_COLD").unwrap().unwrap();
+        assert_eq!(cold.value, Some(Arc::new(vec![1; 1024])));
+        assert_eq!(cache_service.disk_used(), 1024);
+    }
+
+    #[test]
+    fn test_remove_expired_error() {
+        let mut memdb = FastDB::default();
+        let root = std::env::temp_dir().join("rust_fast_cache_remove_expired_error");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("GOOD")).unwrap();
+        std::fs::write(root.join("GOOD").join("cachefile"), vec![0; 1024]).unwrap();
+        // A file where the folder should be, so it can't be removed as one.
+        std::fs::write(root.join("BROKEN"), vec![0; 16]).unwrap();
+
+        let now = get_nano_time();
+        let mut mem_size = 0;
+        for key in &["GOOD", "BROKEN"] {
+            let item = DatabaseItem {
+                value: None,
+                last_access: now - 10,
+                filepath: Some(root.join(key).join("cachefile")),
+                ..DatabaseItem::default()
+            };
+            mem_size += item.get_mem_size() + entry_overhead(key);
+            memdb.set(String::from(*key), item).unwrap();
+        }
+
+        assert_eq!(memdb.remove_expired(now, 0), (mem_size, 1024));
+        assert!(memdb.is_empty());
+        assert!(!root.join("GOOD").exists());
+    }
+
+    #[test]
+    fn test_index_overhead_tracking() {
+        use rust_fast_cache::memdb::memory_database::DefaultHashBuilder;
+
+        let mut memdb = FastDB::with_capacity_and_hasher(1, 1000, DefaultHashBuilder::default());
+        let empty = memdb.index_overhead_bytes();
+        assert!(empty > 0);
+
+        for i in 0..100 {
+            memdb.set(format!("TRACKED_{}", i), DatabaseItem::new(vec![0; 16])).unwrap();
+        }
+        let filled = memdb.index_overhead_bytes();
+        assert!(filled < empty);
+
+        // Replacing an item doesn't change the allocations.
+        memdb.set(String::from("TRACKED_0"), DatabaseItem::new(vec![1; 16])).unwrap();
+        assert_eq!(memdb.index_overhead_bytes(), filled);
+
+        for i in 0..50 {
+            memdb.del(&format!("TRACKED_{}", i)).unwrap();
+        }
+        assert!(memdb.index_overhead_bytes() > filled);
+        memdb.drain();
+        assert!(memdb.index_overhead_bytes() >= empty);
+    }
+
+    #[test]
+    fn test_expiry_sweeper_coarse() {
+        let mut cache_service = test_cache_at("expiry_sweeper_coarse");
+        cache_service.set_time_resolution(TimeResolution::Coarse);
+        cache_service.set_decache_age(Duration::from_secs(1));
+        cache_service.start_expiry_sweeper(Duration::from_millis(10));
+
+        // Longer than `decache_age` after the clock was last refreshed by hand.
+        std::thread::sleep(Duration::from_millis(1200));
+        cache_service.insert_cache_item(String::from("COARSE"), vec![0; 64]).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        cache_service.stop_expiry_sweeper();
+
+        assert!(cache_service.contains_key("COARSE"));
+    }
+
+    #[test]
+    fn test_time_resolution_ttl() {
+        let mut cache_service = test_cache_at("time_resolution_ttl");
+        cache_service.set_time_resolution(TimeResolution::Coarse);
+        cache_service
+            .insert_cache_item_with_ttl(
+                String::from("COARSE_TTL"),
+                vec![1],
+                Duration::from_millis(50),
+            )
+            .unwrap();
+        cache_service
+            .insert_cache_item(String::from("COARSE_KEPT"), vec![2])
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        // Until the clock is refreshed, no time has passed for the cache.
+        assert!(cache_service.touch("COARSE_TTL"));
+        cache_service.refresh_clock();
+        assert_eq!(cache_service.get_cache_value("COARSE_TTL").unwrap(), None);
+        assert_eq!(cache_service.get_cache_value("COARSE_KEPT").unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let path = std::env::temp_dir().join("rust_fast_cache_shutdown");
+        let mut cache_service = test_cache_at("shutdown");
+        cache_service.start_expiry_sweeper(Duration::from_millis(1));
+        cache_service.start_stats_reporter(Duration::from_millis(1));
+        for i in 0..3 {
+            cache_service
+                .insert_cache_item(format!("QUEUED_{}", i), vec![i as u8; 256])
+                .unwrap();
+        }
+        cache_service
+            .insert_cache_item_to_disk(String::from("QUEUED_DISK"), vec![9; 256])
+            .unwrap();
+
+        cache_service.shutdown().unwrap();
+
+        let mut recovered = Cache::default();
+        recovered.set_cache_path(String::from(path.to_str().unwrap()), false).unwrap();
+        assert_eq!(recovered.rebuild_from_disk().unwrap(), 4);
+        for i in 0..3 {
+            assert_eq!(
+                recovered.peek(&format!("QUEUED_{}", i)).unwrap(),
+                Some(vec![i as u8; 256])
+            );
+        }
+        assert_eq!(recovered.peek("QUEUED_DISK").unwrap(), Some(vec![9; 256]));
+    }
+
+    #[test]
+    fn test_get_full_expired() {
+        let mut cache_service = test_cache_at("get_full_expired");
+        cache_service
+            .insert_cache_item_with_ttl(String::from("SHORT"), vec![1], Duration::from_millis(20))
+            .unwrap();
+        cache_service.insert_cache_item(String::from("LONG"), vec![2]).unwrap();
+
+        assert!(cache_service.get_full("SHORT").unwrap().is_some());
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert_eq!(cache_service.peek("SHORT").unwrap(), None);
+        assert!(cache_service.contains_key("SHORT"));
+        assert!(cache_service.get_full("SHORT").unwrap().is_none());
+        assert!(!cache_service.contains_key("SHORT"));
+        assert_eq!(cache_service.get_full("LONG").unwrap().unwrap().0, vec![2]);
+
+        let stats = cache_service.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+    }
+
+    #[test]
+    fn test_dedup_identical_ttl() {
+        let mut cache_service = test_cache_at("dedup_identical_ttl");
+        cache_service.set_dedup_identical(true);
+        let ttl = Duration::from_millis(60);
+
+        cache_service
+            .insert_cache_item_with_ttl(String::from("RENEWED"), vec![1; 16], ttl)
+            .unwrap();
+        let first = cache_service.get_cache_item("RENEWED").unwrap().unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        cache_service
+            .insert_cache_item_with_ttl(String::from("RENEWED"), vec![1; 16], ttl)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+
+        // 80ms after the first insert, but only 40ms after the skipped one.
+        let renewed = cache_service.get_cache_item("RENEWED").unwrap().unwrap();
+        assert!(renewed.last_access > first.last_access);
+        assert_eq!(renewed.created_at, first.created_at);
+
+        // A different ttl isn't skipped.
+        cache_service
+            .insert_cache_item(String::from("RENEWED"), vec![1; 16])
+            .unwrap();
+        assert_eq!(cache_service.get_cache_item("RENEWED").unwrap().unwrap().ttl, None);
+    }
+
+    #[test]
+    fn test_dedup_identical_to_disk() {
+        let mut cache_service = test_cache_at("dedup_identical_to_disk");
+        let path = std::env::temp_dir().join("rust_fast_cache_dedup_identical_to_disk");
+        cache_service.set_dedup_identical(true);
+
+        cache_service.insert_cache_item(String::from("MOVED"), vec![1; 16]).unwrap();
+        cache_service
+            .insert_cache_item_to_disk(String::from("MOVED"), vec![1; 16])
+            .unwrap();
+        let item = cache_service.get_cache_item("MOVED").unwrap().unwrap();
+        assert!(item.value.is_none());
+        assert!(path.join("MOVED").join("cachefile").exists());
+
+        let written = std::fs::metadata(path.join("MOVED").join("cachefile"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        cache_service
+            .insert_cache_item_to_disk(String::from("MOVED"), vec![1; 16])
+            .unwrap();
+        let rewritten = std::fs::metadata(path.join("MOVED").join("cachefile"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(written, rewritten);
+    }
+
+    #[test]
+    fn test_insert_many_oversized_to_disk() {
+        let mut cache_service = test_cache_at("insert_many_oversized_to_disk");
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_insert_many_oversized_to_disk");
+        cache_service.resize_cache(Some(4 * 1024), None, Some(CleanseStrategy::LastAccess)).unwrap();
+
+        let items = vec![
+            (String::from("MANY_0"), vec![0; 1000]),
+            (String::from("MANY_HUGE"), vec![1; 8192]),
+            (String::from("MANY_1"), vec![2; 1000]),
+        ];
+        cache_service.insert_many(items).unwrap();
+
+        // Too large for memory on its own, so it doesn't push the others out.
+        assert!(cache_path.join("MANY_HUGE").exists());
+        assert!(!cache_path.join("MANY_0").exists());
+        assert!(!cache_path.join("MANY_1").exists());
+        assert!(cache_service.memory_used() <= 4 * 1024);
+        assert!(cache_service.get_cache_item("MANY_HUGE").unwrap().unwrap().value.is_none());
+        assert_eq!(
+            cache_service.get_cache_value("MANY_HUGE").unwrap(),
+            Some(vec![1; 8192])
+        );
+    }
+
+    #[test]
+    fn test_get_many_expired() {
+        let mut cache_service = test_cache_at("get_many_expired");
+        cache_service.set_decache_age(Duration::from_secs(1));
+        cache_service.insert_cache_item(String::from("MANY_IDLE"), vec![1; 64]).unwrap();
+        std::thread::sleep(Duration::from_millis(1100));
+        cache_service.insert_cache_item(String::from("MANY_FRESH"), vec![2; 64]).unwrap();
+
+        let keys = vec![
+            String::from("MANY_IDLE"),
+            String::from("MANY_FRESH"),
+            String::from("MANY_IDLE"),
+        ];
+        let values = cache_service.get_many(&keys).unwrap();
+        assert_eq!(values, vec![None, Some(vec![2; 64]), None]);
+        assert!(!cache_service.contains_key("MANY_IDLE"));
+        assert_eq!(cache_service.len(), 1);
+        let stats = cache_service.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+
+        cache_service.remove_cache_item("MANY_FRESH").unwrap();
+        assert_eq!(cache_service.memory_used(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_cache_memory_get_unlocked() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut cache_service = test_cache_at("async_cache_memory_get_unlocked");
+        cache_service.insert_cache_item(String::from("ASYNC_MEM"), vec![1; 64]).unwrap();
+        let cache_service = cache_service.into_async();
+
+        // Held like a blocking job writing to disk would, the memory get mustn't wait for it.
+        let guard = cache_service.lock();
+        let value = runtime
+            .block_on(cache_service.get_cache_value_async("ASYNC_MEM"))
+            .unwrap();
+        assert_eq!(value, Some(vec![1; 64]));
+        let stats = guard.stats();
+        assert_eq!((stats.mem_hits, stats.misses), (1, 0));
+    }
+
+    #[test]
+    fn test_namespace_and_key_with_same_name() {
+        let mut cache_service = test_cache_at("namespace_and_key_with_same_name");
+        let cache_path = std::env::temp_dir().join("rust_fast_cache_namespace_and_key_with_same_name");
+        cache_service.insert_cache_item(String::from("users"), vec![1; 64]).unwrap();
+        cache_service.insert_cache_item_ns("users", "1", vec![2; 64]).unwrap();
+        cache_service.flush_to_disk().unwrap();
+        assert!(cache_path.join("users").join("cachefile").exists());
+        assert!(cache_path.join("%ns").join("users").join("1").join("cachefile").exists());
+
+        cache_service.evict_namespace("users").unwrap();
+        assert!(!cache_path.join("%ns").exists());
+        assert!(cache_path.join("users").join("cachefile").exists());
+        assert_eq!(cache_service.get_cache_value("users").unwrap(), Some(vec![1; 64]));
+        assert_eq!(cache_service.disk_used(), 64);
+
+        // A fresh cache on the same path finds both kinds of folders.
+        cache_service.insert_cache_item_ns("users", "2", vec![3; 64]).unwrap();
+        cache_service.flush_to_disk().unwrap();
+        let mut rebuilt = Cache::default();
+        rebuilt.set_cache_path(String::from(cache_path.to_str().unwrap()), false).unwrap();
+        assert_eq!(rebuilt.rebuild_from_disk().unwrap(), 2);
+        assert_eq!(rebuilt.peek("users").unwrap(), Some(vec![1; 64]));
+        assert_eq!(rebuilt.peek("users/2").unwrap(), Some(vec![3; 64]));
+    }
+
+    #[test]
+    fn test_remove_entries_error() {
+        let mut memdb = FastDB::default();
+        let root = std::env::temp_dir().join("rust_fast_cache_remove_entries_error");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("GOOD")).unwrap();
+        std::fs::write(root.join("GOOD").join("cachefile"), vec![0; 1024]).unwrap();
+        // A file where the folder should be, so it can't be removed as one.
+        std::fs::write(root.join("BROKEN"), vec![0; 16]).unwrap();
+
+        let now = get_nano_time();
+        let mut mem_size = 0;
+        for (i, key) in ["BROKEN", "GOOD"].iter().enumerate() {
+            let item = DatabaseItem {
+                value: None,
+                last_access: now + i as u128,
+                filepath: Some(root.join(key).join("cachefile")),
+                ..DatabaseItem::default()
+            };
+            mem_size += item.get_mem_size() + entry_overhead(key);
+            memdb.set(String::from(*key), item).unwrap();
+        }
+
+        // The failed removal of BROKEN doesn't lose the accounting of GOOD after it.
+        assert_eq!(memdb.remove_entries(&CleanseStrategy::LastAccess, 2), (mem_size, 1024));
+        assert!(memdb.is_empty());
+        assert!(!root.join("GOOD").exists());
+    }
+
+    #[test]
+    fn test_background_tasks_leave_threadpool_free() {
+        let mut cache_service = test_cache_at("background_tasks_leave_threadpool_free");
+        cache_service.set_management_threads(2);
+        cache_service.start_expiry_sweeper(Duration::from_secs(60));
+        cache_service.start_stats_reporter(Duration::from_secs(60));
+        for i in 0..10 {
+            cache_service.insert_cache_item(format!("POOL_{}", i), vec![0; 1024]).unwrap();
+        }
+
+        // The spill runs on the threadpool, which the running tasks mustn't occupy.
+        cache_service.resize_cache(Some(0), None, Some(CleanseStrategy::LastAccess)).unwrap();
+        assert_eq!(cache_service.disk_used(), 10 * 1024);
+        cache_service.shutdown().unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_cache_time_resolution() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut cache_service = test_cache_at("async_cache_time_resolution");
+        cache_service.set_time_resolution(TimeResolution::Coarse);
+        cache_service.insert_cache_item(String::from("ASYNC_COARSE"), vec![1; 64]).unwrap();
+        let inserted = cache_service.iter_metadata()[0].1;
+        let cache_service = cache_service.into_async();
+        let clock_reads = cache_service.lock().stats().clock_reads;
+
+        // The memory get stamps the cached time, without reading the clock.
+        std::thread::sleep(Duration::from_millis(5));
+        let value = runtime
+            .block_on(cache_service.get_cache_value_async("ASYNC_COARSE"))
+            .unwrap();
+        assert_eq!(value, Some(vec![1; 64]));
+        assert_eq!(cache_service.lock().iter_metadata()[0].1, inserted);
+        assert_eq!(cache_service.lock().stats().clock_reads, clock_reads);
+
+        cache_service.lock().refresh_clock();
+        runtime
+            .block_on(cache_service.get_cache_value_async("ASYNC_COARSE"))
+            .unwrap();
+        assert!(cache_service.lock().iter_metadata()[0].1 > inserted);
     }
 }